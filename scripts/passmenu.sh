@@ -7,6 +7,7 @@ password_files=( "$prefix"/**/*.gpg )
 password_files=( "${password_files[@]#"$prefix"/}" )
 password_files=( "${password_files[@]%.gpg}" )
 
-password=$(printf '%s\n' "${password_files[@]}" | kickoff --stdout --from-stdin)
+# Exits with 1 if cancelled and 2 for a name that is not in the list
+password=$(printf '%s\n' "${password_files[@]}" | kickoff --stdout --from-stdin) || exit
 
 pass -c "$password"
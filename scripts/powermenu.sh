@@ -0,0 +1,25 @@
+#!/usr/bin/env bash
+# Multi-level menu for `kickoff --script scripts/powermenu.sh`.
+# Called without an argument for the first list, then with each selected value.
+# Kickoff closes once nothing is printed.
+
+case "$1" in
+"")
+    printf 'Lock\nPower\n'
+    ;;
+Power)
+    printf 'Shutdown\nReboot\nBack\n'
+    ;;
+Back)
+    printf 'Lock\nPower\n'
+    ;;
+Lock)
+    loginctl lock-session
+    ;;
+Shutdown)
+    systemctl poweroff
+    ;;
+Reboot)
+    systemctl reboot
+    ;;
+esac
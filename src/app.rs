@@ -1,19 +1,48 @@
 use std::time::{Duration, Instant};
-use std::{cmp, process};
+use std::path::Path;
+use std::process::Command;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::{cmp, io, thread};
 
 use crate::calculator;
+use crate::script;
+use crate::terminal;
 use crate::config::{Config, History};
-use crate::font::Font;
-use crate::selection::{Element, ElementList};
-use crate::Args;
-use image::{ImageBuffer, RgbaImage};
+use crate::font::{Font, Highlighted};
+use crate::icon::Icons;
+use crate::selection::{self, Element, ElementList, JsonItem};
+use crate::{Args, OutputFormat};
+use image::{imageops, ImageBuffer, RgbaImage};
 use log::{debug, error};
-use nix::{
-    sys::wait::{waitpid, WaitPidFlag, WaitStatus},
-    unistd::{fork, ForkResult},
-};
 use notify_rust::Notification;
 
+/// How the window was left, reported as the exit status with `--stdout`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Outcome {
+    #[default]
+    Cancelled,
+    Selected,
+    /// Text typed by the user, which is not in the list
+    CustomInput,
+    /// Left with one of the custom keybindings, by their position in the config
+    CustomKey(u8),
+}
+
+impl Outcome {
+    pub const fn exit_code(self) -> i32 {
+        match self {
+            Self::Selected => 0,
+            Self::Cancelled => 1,
+            Self::CustomInput => 2,
+            Self::CustomKey(index) => 10 + index as i32,
+        }
+    }
+}
+
+const SPINNER: [char; 4] = ['-', '\\', '|', '/'];
+/// Shown for every typed character with `--password`.
+const PASSWORD_BULLET: &str = "\u{2022}";
+
 pub struct App {
     pub config: Config,
     pub select_index: usize,
@@ -25,6 +54,19 @@ pub struct App {
     pub last_search_result: Vec<usize>,
     pub args: Args,
     pub calculator_result: Option<(String, f64)>, // (expression, result)
+    pub icons: Icons,
+    /// Reserve space for icons, only if any entry has one
+    pub show_icons: bool,
+    pub outcome: Outcome,
+    /// Elements from stdin or a script are still arriving
+    pub loading: bool,
+    /// Output of the script of `--script` mode, while it is running
+    script_output: Option<Receiver<io::Result<Vec<Element>>>>,
+    start: Instant,
+    /// The entry of `--select` or `--select-index` was not highlighted yet
+    pending_select: bool,
+    /// Entries marked with `--multi-select`, as indices into `all_entries` in the order they were marked
+    pub marked: Vec<usize>,
 }
 
 impl App {
@@ -35,7 +77,10 @@ impl App {
         font: Font,
         history: Option<History>,
     ) -> Self {
+        let show_icons = show_icons(&config, &all_entries);
         let mut app = Self {
+            icons: Icons::new(config.icons.clone()),
+            show_icons,
             args,
             config,
             font,
@@ -46,12 +91,75 @@ impl App {
             query: String::new(),
             last_search_result: Vec::new(),
             calculator_result: None,
+            outcome: Outcome::default(),
+            marked: Vec::new(),
+            loading: false,
+            script_output: None,
+            start: Instant::now(),
+            pending_select: false,
         };
-        app.search();
+        app.prefill();
 
         app
     }
 
+    /// Replaces the entries and history and clears the query, so a daemon
+    /// starts every session like a fresh instance.
+    pub fn reset(&mut self, all_entries: ElementList, history: Option<History>) {
+        self.replace_entries(all_entries);
+        self.history = history;
+        self.prefill();
+    }
+
+    /// Shows the next list of a script. The query and selection given on the
+    /// command line only apply to the first list.
+    fn next_level(&mut self, all_entries: ElementList) {
+        self.replace_entries(all_entries);
+        self.query.clear();
+        self.pending_select = false;
+        self.search();
+    }
+
+    fn replace_entries(&mut self, all_entries: ElementList) {
+        self.show_icons = show_icons(&self.config, &all_entries);
+        self.all_entries = all_entries;
+        self.outcome = Outcome::default();
+        self.marked.clear();
+    }
+
+    /// Starts over with the query and selection given on the command line.
+    fn prefill(&mut self) {
+        self.query = self.args.query.clone().unwrap_or_default();
+        self.search();
+        self.pending_select = self.args.select.is_some() || self.args.select_index.is_some();
+        self.apply_select();
+    }
+
+    /// Highlights the entry of `--select` or `--select-index` once it is listed,
+    /// unless the user moved on already.
+    fn apply_select(&mut self) {
+        if !self.pending_select {
+            return;
+        }
+        let calculator_count = usize::from(self.calculator_result.is_some());
+        let position = if let Some(name) = &self.args.select {
+            let all_entries = self.all_entries.as_ref_vec();
+            self.last_search_result
+                .iter()
+                .position(|index| all_entries[*index].name == *name)
+                .map(|position| position + calculator_count)
+        } else {
+            self.args
+                .select_index
+                .filter(|index| *index < self.get_total_results())
+        };
+        if let Some(position) = position.filter(|position| !self.is_header(*position)) {
+            self.select_index = position;
+            self.select_input = false;
+            self.pending_select = false;
+        }
+    }
+
     pub fn complete(&mut self) {
         if !self.select_input {
             let app = (*self
@@ -73,11 +181,20 @@ impl App {
     }
 
     pub fn nav_up(&mut self, distance: usize) {
+        self.pending_select = false;
+        let previous = self.select_index;
         if self.select_index > 0 {
             self.select_index = self.select_index.saturating_sub(distance);
         } else if !self.query.is_empty() {
             self.select_input = true;
         }
+        // Only the typed text is left above headers at the top
+        let fallback = if self.query.is_empty() {
+            Some(previous)
+        } else {
+            None
+        };
+        self.skip_headers(false, fallback);
     }
     
     fn get_total_results(&self) -> usize {
@@ -86,25 +203,73 @@ impl App {
     }
 
     pub fn nav_down(&mut self, distance: usize) {
+        self.pending_select = false;
         if self.select_input {
             if self.calculator_result.is_some() || !self.last_search_result.is_empty() {
                 self.select_input = false;
                 self.select_index = 0;
+                self.skip_headers(true, None);
             }
         } else {
+            let previous = self.select_index;
             let total_results = self.get_total_results();
             if self.select_index < total_results.saturating_sub(distance) {
                 self.select_index += distance;
             }
+            self.skip_headers(true, Some(previous));
+        }
+    }
+
+    /// Whether the entry at `position` of the listed results is a header.
+    fn is_header(&self, position: usize) -> bool {
+        position
+            .checked_sub(usize::from(self.calculator_result.is_some()))
+            .and_then(|position| self.last_search_result.get(position))
+            .and_then(|index| self.all_entries.get(*index))
+            .is_some_and(|element| element.header)
+    }
+
+    /// Moves the highlight past headers in the given direction. Goes back to
+    /// `fallback` if there is no entry left that way, or to the typed text without one.
+    fn skip_headers(&mut self, forward: bool, fallback: Option<usize>) {
+        let total_results = self.get_total_results();
+        while !self.select_input && self.is_header(self.select_index) {
+            if forward && self.select_index + 1 < total_results {
+                self.select_index += 1;
+            } else if !forward && self.select_index > 0 {
+                self.select_index -= 1;
+            } else if let Some(fallback) = fallback {
+                self.select_index = fallback;
+                break;
+            } else {
+                self.select_input = true;
+            }
+        }
+    }
+
+    /// Marks or unmarks the highlighted entry and moves on to the next one.
+    pub fn toggle_mark(&mut self) {
+        if !self.args.multi_select {
+            return;
+        }
+        if let Some(index) = self.selected_index() {
+            if let Some(position) = self.marked.iter().position(|marked| *marked == index) {
+                self.marked.remove(position);
+            } else {
+                self.marked.push(index);
+            }
+            self.nav_down(1);
         }
     }
 
     pub fn delete(&mut self) {
+        self.pending_select = false;
         self.query.pop();
         self.search();
     }
 
     pub fn delete_word(&mut self) {
+        self.pending_select = false;
         self.query.pop();
         loop {
             let removed_char = self.query.pop();
@@ -115,76 +280,245 @@ impl App {
         self.search();
     }
 
-    pub fn execute(&mut self) {
+    /// Executes or prints the selection. Returns whether the window should close,
+    /// which it does not while a script of `--script` mode has more levels.
+    pub fn execute(&mut self) -> bool {
+        self.execute_selection(false)
+    }
+
+    /// Like `execute`, but runs the selection inside a terminal emulator.
+    pub fn execute_in_terminal(&mut self) -> bool {
+        self.execute_selection(true)
+    }
+
+    fn execute_selection(&mut self, in_terminal: bool) -> bool {
+        if self.rejects_input() || self.script_output.is_some() {
+            return false;
+        }
+        self.outcome = if self.select_input && self.marked.is_empty() {
+            Outcome::CustomInput
+        } else {
+            Outcome::Selected
+        };
         // Check if we're selecting a calculator result
-        if !self.select_input && self.calculator_result.is_some() && self.select_index == 0 {
-            if let Some((_, result)) = &self.calculator_result {
-                let result_str = calculator::format_result(*result);
-                
-                // Copy to clipboard using wl-clipboard-rs
-                use wl_clipboard_rs::copy::{MimeType, Options, Source};
-                let opts = Options::new();
-                if let Err(e) = opts.copy(Source::Bytes(result_str.as_bytes().into()), MimeType::Text) {
-                    log::error!("Failed to copy to clipboard: {}", e);
-                }
-                return;
+        if let Some(element) = self.selected_calculation() {
+            if self.args.stdout {
+                self.print_calculation(&element);
+                return true;
+            }
+
+            // Copy to clipboard using wl-clipboard-rs
+            use wl_clipboard_rs::copy::{MimeType, Options, Source};
+            let opts = Options::new();
+            if let Err(e) = opts.copy(
+                Source::Bytes(element.value.as_bytes().into()),
+                MimeType::Text,
+            ) {
+                log::error!("Failed to copy to clipboard: {}", e);
             }
+            return true;
         }
         
-        let element = if self.select_input {
-            Element {
+        let Some(mut element) = self.selected_element() else {
+            return true;
+        };
+        if let Some(script) = self.args.script.clone() {
+            return self.run_script(&script, &element);
+        }
+        if self.args.stdout {
+            let elements = self.selected_elements(element);
+            self.print(&elements);
+        } else {
+            element.terminal |= in_terminal;
+            execute(
+                &element,
+                self.history.take(),
+                self.config.terminal.as_deref(),
+            );
+        }
+        true
+    }
+
+    /// Prints the selection and leaves with the exit code of a custom keybinding,
+    /// even if kickoff would execute it otherwise.
+    pub fn execute_custom(&mut self, index: u8) -> bool {
+        if self.rejects_input() || self.script_output.is_some() {
+            return false;
+        }
+        if let Some(element) = self.selected_calculation() {
+            self.outcome = Outcome::CustomKey(index);
+            self.print_calculation(&element);
+            return true;
+        }
+        let Some(element) = self.selected_element() else {
+            return true;
+        };
+        self.outcome = Outcome::CustomKey(index);
+        if let Some(script) = self.args.script.clone() {
+            return self.run_script(&script, &element);
+        }
+        let elements = self.selected_elements(element);
+        self.print(&elements);
+        true
+    }
+
+    /// Starts the script of `--script` mode with the selection in the background,
+    /// so the window keeps responding. Its output is picked up by `poll_script`.
+    fn run_script(&mut self, script: &Path, element: &Element) -> bool {
+        let retv = script::retv(self.outcome);
+        let (sender, receiver) = mpsc::channel();
+        let script = script.to_path_buf();
+        let element = element.clone();
+        let format = self.args.format;
+        thread::spawn(move || {
+            // The window may be gone already, nobody is waiting for the output then
+            let _ = sender.send(script::run(&script, Some(&element), retv, format));
+        });
+        self.script_output = Some(receiver);
+        self.loading = true;
+        false
+    }
+
+    /// Shows the output of a finished script as the next list. Returns whether the
+    /// window should close, which it does once the script prints nothing.
+    pub fn poll_script(&mut self) -> bool {
+        let Some(receiver) = &self.script_output else {
+            return false;
+        };
+        let output = match receiver.try_recv() {
+            Ok(output) => output,
+            Err(TryRecvError::Empty) => return false,
+            Err(TryRecvError::Disconnected) => Err(io::Error::other("script runner stopped")),
+        };
+        self.script_output = None;
+        self.loading = false;
+
+        match output {
+            Ok(elements) if !elements.is_empty() => {
+                self.next_level(ElementList::from(elements));
+                false
+            }
+            Ok(_) => true,
+            Err(e) => {
+                if let Some(script) = &self.args.script {
+                    error!("Failed to run {}: {e}", script.display());
+                }
+                true
+            }
+        }
+    }
+
+    /// Index into `all_entries` of the highlighted entry.
+    fn selected_index(&self) -> Option<usize> {
+        if self.select_input {
+            return None;
+        }
+        // Adjust index for calculator result
+        let actual_index = if self.calculator_result.is_some() {
+            self.select_index.checked_sub(1)?
+        } else {
+            self.select_index
+        };
+        self.last_search_result.get(actual_index).copied()
+    }
+
+    /// The highlighted entry or the typed text, `None` if the calculator result is highlighted.
+    fn selected_element(&self) -> Option<Element> {
+        if self.select_input {
+            Some(Element {
                 name: self.query.to_string(),
                 value: self.query.to_string(),
-                base_score: 0,
-            }
+                ..Element::default()
+            })
         } else {
-            // Adjust index for calculator result
-            let actual_index = if self.calculator_result.is_some() {
-                if self.select_index == 0 {
-                    // This should have been handled above, but just in case
-                    return;
-                } else {
-                    self.select_index - 1
-                }
-            } else {
-                self.select_index
-            };
-            
-            (*self
-                .all_entries
-                .as_ref_vec()
-                .get(*self.last_search_result.get(actual_index).unwrap())
-                .unwrap())
-            .clone()
+            self.selected_index()
+                .and_then(|index| self.all_entries.as_ref_vec().get(index).copied())
+                .cloned()
+        }
+    }
+
+    /// The calculator result, if it is highlighted.
+    fn selected_calculation(&self) -> Option<Element> {
+        if self.select_input || self.select_index != 0 {
+            return None;
+        }
+        self.calculator_result
+            .as_ref()
+            .map(|(expression, result)| calculator_element(expression, *result))
+    }
+
+    /// Prints a calculator result like a selection, without remembering it in the history.
+    fn print_calculation(&mut self, element: &Element) {
+        let history = self.history.take();
+        self.print(std::slice::from_ref(element));
+        self.history = history;
+    }
+
+    /// The marked entries if there are any, otherwise only the given selection.
+    fn selected_elements(&self, selected: Element) -> Vec<Element> {
+        if self.marked.is_empty() {
+            return vec![selected];
+        }
+        let all_entries = self.all_entries.as_ref_vec();
+        self.marked
+            .iter()
+            .filter_map(|index| all_entries.get(*index).copied())
+            .cloned()
+            .collect()
+    }
+
+    /// Typed text is only accepted as selection without `--only-match`.
+    fn rejects_input(&self) -> bool {
+        self.args.only_match && self.select_input && self.marked.is_empty()
+    }
+
+    fn print(&mut self, elements: &[Element]) {
+        let terminator = if self.args.print0 {
+            "\0"
+        } else if self.args.dmenu
+            || self.args.multi_select
+            || self.args.print_query
+            || self.args.output == OutputFormat::Json
+        {
+            "\n"
+        } else {
+            ""
         };
-        if self.args.stdout {
-            print!("{}", element.value);
-            if let Some(mut history) = self.history.take() {
-                history.inc(&element);
-                history.save().unwrap();
-            }
+        if self.args.print_query {
+            let separator = if self.args.print0 { "\0" } else { "\n" };
+            print!("{}{separator}", self.query);
+        }
+        // Typed text leaves the selection empty, so it is told apart from an entry
+        if self.args.print_query && self.select_input && self.marked.is_empty() {
+            print!("{terminator}");
         } else {
-            execute(&element, self.history.take());
+            print_elements(elements, self.args.output, terminator);
+        }
+        if let Some(mut history) = self.history.take() {
+            for element in elements {
+                history.inc(element);
+            }
+            history.save().unwrap();
         }
     }
 
     pub fn insert(&mut self, input: &str) {
+        self.pending_select = false;
         self.query.push_str(input);
         self.search();
     }
 
     pub fn search(&mut self) {
-        self.last_search_result = Vec::new();
         self.calculator_result = None;
         
         // Check if query is a math expression
-        if calculator::is_math_expression(&self.query) {
+        if self.args.calculator() && calculator::is_math_expression(&self.query) {
             if let Ok(result) = calculator::evaluate(&self.query) {
                 self.calculator_result = Some((self.query.clone(), result));
             }
         }
         
-        let search_results = self.all_entries.search(&self.query);
+        self.last_search_result = self.all_entries.search(&self.query, &self.config.search);
 
         self.select_input = false;
         self.select_index = 0;
@@ -192,26 +526,51 @@ impl App {
         // If we have a calculator result, start with that selected
         if self.calculator_result.is_some() {
             // Calculator result will be at index 0, regular results follow
-        } else if search_results.is_empty() {
+        } else if self.last_search_result.is_empty() {
             self.select_input = true;
         }
+        self.skip_headers(true, None);
+    }
 
-        // Build list of indices to search results
-        let all_entries = self.all_entries.as_ref_vec();
-        for entry in search_results {
-            let index = all_entries.iter().position(|x| x == &entry);
-            if let Some(i) = index {
-                self.last_search_result.push(i);
-            }
+    /// Adds elements streamed in while the window is open, keeping the highlighted entry.
+    pub fn extend(&mut self, elements: Vec<Element>) {
+        // The typed text is only chosen over the entries if it was highlighted
+        // while there were entries to choose from
+        let select_input =
+            self.select_input && !self.query.is_empty() && self.get_total_results() > 0;
+        let selected = self.selected_index();
+        self.show_icons |=
+            self.config.icons.enabled && elements.iter().any(|entry| entry.icon.is_some());
+        self.all_entries.extend(elements, self.history.as_ref());
+        self.search();
+        if self.pending_select {
+            self.apply_select();
+            return;
+        }
+        if select_input {
+            self.select_input = true;
+            return;
+        }
+
+        let position = selected.and_then(|selected| {
+            self.last_search_result
+                .iter()
+                .position(|index| *index == selected)
+        });
+        if let Some(position) = position {
+            self.select_input = false;
+            self.select_index = position + usize::from(self.calculator_result.is_some());
         }
     }
 
     pub fn draw(&mut self, width: u32, height: u32, scale: i32) -> RgbaImage {
         let frame_draw_start = Instant::now();
+        self.icons.receive();
+        let all_entries = self.all_entries.as_ref_vec();
         let search_results: Vec<&Element> = self
             .last_search_result
             .iter()
-            .map(|index| *self.all_entries.as_ref_vec().get(*index).unwrap())
+            .map(|index| all_entries[*index])
             .collect();
 
         self.font.set_scale(scale);
@@ -244,27 +603,76 @@ impl App {
             } else {
                 &self.config.colors.text_query
             };
+            let query = if self.args.password {
+                PASSWORD_BULLET.repeat(self.query.chars().count())
+            } else {
+                self.query.clone()
+            };
             self.font.render(
-                &self.query,
+                &query,
                 color,
                 &mut img,
                 padding + prompt_width,
                 padding,
                 None,
             );
+        } else if let Some(placeholder) = &self.args.placeholder {
+            self.font.render(
+                placeholder,
+                &self.config.colors.placeholder,
+                &mut img,
+                padding + prompt_width,
+                padding,
+                None,
+            );
+        }
+
+        // Count matches of the entries from stdin or a script, spinning while more are coming
+        if self.args.from_stdin || self.args.script.is_some() {
+            let mut counter = format!(
+                "{}/{}",
+                self.last_search_result.len(),
+                self.all_entries.len()
+            );
+            if self.loading {
+                let frame = self.start.elapsed().as_millis() / 100 % SPINNER.len() as u128;
+                counter.insert_str(0, &format!("{} ", SPINNER[frame as usize]));
+            }
+            let counter_x = width.saturating_sub(padding + self.font.width(&counter));
+            self.font.render(
+                &counter,
+                &self.config.colors.prompt,
+                &mut img,
+                counter_x,
+                padding,
+                None,
+            );
         }
 
         let spacer = (1.5 * font_size) as u32;
-        let max_entries = ((height.saturating_sub(2 * padding).saturating_sub(spacer)) as f32
+        let line_height = (font_size * 1.2) as u32;
+        let mut max_entries = ((height.saturating_sub(2 * padding).saturating_sub(spacer)) as f32
             / (font_size * 1.2)) as usize;
-        let offset = if self.select_index > (max_entries / 2) {
-            self.select_index - max_entries / 2
+        if let Some(lines) = self.args.lines {
+            max_entries = max_entries.min(lines);
+        }
+        let offset = self.select_index.saturating_sub(max_entries / 2);
+
+        let icon_size = self.icons.size(scale as u32);
+        let marker_width = if self.args.multi_select {
+            (font_size * 0.8) as u32
         } else {
             0
         };
+        let text_offset = if self.show_icons {
+            marker_width + icon_size + (font_size * 0.4) as u32
+        } else {
+            marker_width
+        };
+        let text_width = width.saturating_sub(padding * 2 + text_offset) as usize;
 
         let mut display_index = 0;
-        
+
         // Display calculator result first if it exists
         if let Some((expr, result)) = &self.calculator_result {
             let result_str = calculator::format_result(*result);
@@ -278,13 +686,13 @@ impl App {
                 &display_text,
                 color,
                 &mut img,
-                padding,
-                padding + spacer + display_index as u32 * (font_size * 1.2) as u32,
-                Some((width - (padding * 2)) as usize),
+                padding + text_offset,
+                padding + spacer + display_index as u32 * line_height,
+                Some(text_width),
             );
             display_index += 1;
         }
-        
+
         // Display regular search results
         for (i, matched) in search_results
             .iter()
@@ -295,25 +703,56 @@ impl App {
             if display_index >= max_entries {
                 break;
             }
-            
+
             let actual_selection_index = if self.calculator_result.is_some() {
                 i + 1
             } else {
                 i
             };
-            
+
             let color = if actual_selection_index == self.select_index && !self.select_input {
                 &self.config.colors.text_selected
+            } else if matched.header {
+                &self.config.colors.placeholder
             } else {
                 &self.config.colors.text
             };
+            let y = padding + spacer + display_index as u32 * line_height;
+            if self.marked.contains(&self.last_search_result[i]) {
+                self.font.render(
+                    "*",
+                    color,
+                    &mut img,
+                    padding,
+                    y,
+                    Some(marker_width as usize),
+                );
+            }
+            if self.show_icons {
+                if let Some(icon) = matched
+                    .icon
+                    .as_ref()
+                    .and_then(|icon| self.icons.get(icon, scale as u32))
+                {
+                    // Center the icon on the line
+                    let icon_y = i64::from(y) + (i64::from(line_height) - i64::from(icon_size)) / 2;
+                    let icon_x = i64::from(padding + marker_width);
+                    imageops::overlay(&mut img, icon, icon_x, icon_y);
+                }
+            }
+            let positions =
+                selection::match_positions(&matched.name, &self.query, &self.config.search);
             self.font.render(
                 &matched.name,
-                color,
+                &Highlighted {
+                    color,
+                    highlight: &self.config.colors.text_match,
+                    positions: &positions,
+                },
                 &mut img,
-                padding,
-                padding + spacer + display_index as u32 * (font_size * 1.2) as u32,
-                Some((width - (padding * 2)) as usize),
+                padding + text_offset,
+                y,
+                Some(text_width),
             );
             display_index += 1;
         }
@@ -325,48 +764,158 @@ impl App {
     }
 }
 
-fn execute(elem: &Element, history: Option<History>) {
-    match unsafe { fork() } {
-        Ok(ForkResult::Parent { child }) => {
-            // We can't make that to long, since for some reason, even if this would be after a fork and the main programm exits,
-            // wayland keeps the window alive
-            std::thread::sleep(Duration::new(0, 100_000_000));
-            match waitpid(child, Some(WaitPidFlag::WNOHANG)) {
-                Ok(WaitStatus::StillAlive | WaitStatus::Exited(_, 0)) => {
-                    if let Some(mut history) = history {
-                        history.inc(elem);
-                        match history.save() {
-                            Ok(()) => {}
-                            Err(e) => {
-                                error!("{e}");
-                            }
-                        };
-                    }
-                }
-                Ok(_) => {
-                    /* Every non 0 statuscode holds no information since it's
-                    origin can be the started application or a file not found error.
-                    In either case the error has already been logged and does not
-                    need to be handled here. */
-                }
-                Err(err) => error!("{err}"),
-            }
+/// Reserve space for icons only if they are enabled and any entry has one.
+fn show_icons(config: &Config, entries: &ElementList) -> bool {
+    config.icons.enabled
+        && entries
+            .as_ref_vec()
+            .iter()
+            .any(|entry| entry.icon.is_some())
+}
+
+fn execute(elem: &Element, history: Option<History>, terminal: Option<&str>) {
+    let mut argv = elem
+        .argv
+        .clone()
+        .unwrap_or_else(|| vec!["sh".to_string(), "-c".to_string(), elem.value.clone()]);
+    if elem.terminal {
+        match terminal::command(terminal) {
+            Some(terminal) => argv = terminal::wrap(&terminal, &argv),
+            None => error!("No terminal emulator found, set one in the config"),
         }
+    }
 
-        Ok(ForkResult::Child) => {
-            let err = exec::Command::new("sh").args(&["-c", &elem.value]).exec();
+    let mut command = Command::new(&argv[0]);
+    command.args(&argv[1..]);
+    if let Some(dir) = &elem.working_dir {
+        if dir.is_dir() {
+            command.current_dir(dir);
+        } else {
+            error!("Failed to change into {}: not a directory", dir.display());
+        }
+    }
 
-            // Won't be executed when exec was successful
+    // Spawning instead of forking by hand, since a forked child of the daemon's
+    // multi threaded runtime may only make async-signal-safe calls until it execs
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(err) => {
             error!("{err}");
-
-            Notification::new()
+            if let Err(e) = Notification::new()
                 .summary("Kickoff")
                 .body(&format!("{err}"))
                 .timeout(5000)
                 .show()
-                .unwrap();
-            process::exit(2);
+            {
+                error!("{e}");
+            }
+            return;
+        }
+    };
+
+    // Give the program a moment to fail, so a broken entry is not remembered. This
+    // can't take long, since the window stays on screen until it returns
+    std::thread::sleep(Duration::new(0, 100_000_000));
+    match child.try_wait() {
+        // Still running or exited successfully
+        Ok(status) if status.map_or(true, |status| status.success()) => {
+            if let Some(mut history) = history {
+                history.inc(elem);
+                match history.save() {
+                    Ok(()) => {}
+                    Err(e) => {
+                        error!("{e}");
+                    }
+                };
+            }
+        }
+        Ok(_) => {
+            /* Every non 0 statuscode holds no information since it's
+            origin can be the started application or a file not found error.
+            In either case the error has already been logged and does not
+            need to be handled here. */
+        }
+        Err(err) => error!("{err}"),
+    }
+}
+
+/// The result of the calculator as entry, with the result as its value.
+pub fn calculator_element(expression: &str, result: f64) -> Element {
+    let value = calculator::format_result(result);
+    Element {
+        name: format!("{expression} = {value}"),
+        value,
+        ..Element::default()
+    }
+}
+
+/// Prints the value or JSON form of each element, followed by `terminator`.
+pub fn print_elements(elements: &[Element], output: OutputFormat, terminator: &str) {
+    for element in elements {
+        match output {
+            OutputFormat::Value => print!("{}{terminator}", element.value),
+            OutputFormat::Json => match serde_json::to_string(&JsonItem::from(element)) {
+                Ok(json) => print!("{json}{terminator}"),
+                Err(e) => error!("{e}"),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn app(args: &[&str]) -> App {
+        App::new(
+            Args::parse_from(args),
+            Config::default(),
+            ElementList::default(),
+            Font::empty(),
+            None,
+        )
+    }
+
+    fn element(name: &str) -> Element {
+        Element {
+            name: name.to_string(),
+            value: name.to_string(),
+            ..Element::default()
         }
-        Err(e) => error!("{e}"),
+    }
+
+    fn selected(app: &App) -> Option<String> {
+        app.selected_element()
+            .filter(|_| !app.select_input)
+            .map(|element| element.name)
+    }
+
+    #[test]
+    fn extend_highlights_streamed_entries() {
+        // Everything arrives after the window opened with an empty list
+        let mut dmenu = app(&["kickoff", "--dmenu"]);
+        assert!(dmenu.select_input);
+        dmenu.extend(vec![element("shutdown"), element("reboot")]);
+        assert_eq!(selected(&dmenu).as_deref(), Some("shutdown"));
+        dmenu.nav_down(1);
+        dmenu.extend(vec![element("suspend")]);
+        assert_eq!(selected(&dmenu).as_deref(), Some("reboot"));
+
+        // Typed text the user moved up to stays highlighted
+        dmenu.insert("s");
+        dmenu.nav_up(1);
+        dmenu.extend(vec![element("sleep")]);
+        assert!(dmenu.select_input);
+
+        let mut select = app(&["kickoff", "--dmenu", "--select", "reboot"]);
+        select.extend(vec![element("shutdown")]);
+        assert_eq!(selected(&select).as_deref(), Some("shutdown"));
+        select.extend(vec![element("reboot")]);
+        assert_eq!(selected(&select).as_deref(), Some("reboot"));
+
+        let mut index = app(&["kickoff", "--dmenu", "--select-index", "1"]);
+        index.extend(vec![element("shutdown"), element("reboot")]);
+        assert_eq!(selected(&index).as_deref(), Some("reboot"));
     }
 }
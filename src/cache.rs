@@ -0,0 +1,186 @@
+use crate::selection::Element;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use xdg::BaseDirectories;
+
+/// Bumped whenever the layout of `Element` changes, invalidating old caches.
+const VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize, Default)]
+struct CacheFile {
+    version: u32,
+    sources: HashMap<String, CachedSource>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct CachedSource {
+    fingerprint: u64,
+    elements: Vec<Element>,
+}
+
+/// On-disk index of the elements produced by each source, like a single `$PATH`
+/// directory or all desktop files. A source is only rescanned if its fingerprint,
+/// usually derived from modification times, has changed.
+#[derive(Default)]
+pub struct IndexCache {
+    path: Option<PathBuf>,
+    sources: Mutex<HashMap<String, CachedSource>>,
+    dirty: AtomicBool,
+}
+
+impl IndexCache {
+    /// Loads the cache from the XDG cache directory. With `rebuild` set,
+    /// all sources are rescanned and the cache is overwritten.
+    pub fn load(rebuild: bool) -> Self {
+        let path = BaseDirectories::with_prefix("kickoff")
+            .place_cache_file("index.json")
+            .map_err(|e| warn!("Failed to place index cache: {e}"))
+            .ok();
+
+        let file = path
+            .as_ref()
+            .filter(|_| !rebuild)
+            .and_then(|path| fs::read(path).ok())
+            .and_then(|content| {
+                serde_json::from_slice::<CacheFile>(&content)
+                    .map_err(|e| debug!("Ignoring invalid index cache: {e}"))
+                    .ok()
+            })
+            .filter(|file| file.version == VERSION)
+            .unwrap_or_default();
+
+        Self {
+            path,
+            sources: Mutex::new(file.sources),
+            dirty: AtomicBool::new(rebuild),
+        }
+    }
+
+    /// Returns the cached elements of a source, or builds and caches them
+    /// if the fingerprint does not match.
+    pub fn get_or_build<F>(
+        &self,
+        source: &str,
+        fingerprint: u64,
+        build: F,
+    ) -> Result<Vec<Element>, std::io::Error>
+    where
+        F: FnOnce() -> Result<Vec<Element>, std::io::Error>,
+    {
+        let cached = self
+            .sources
+            .lock()
+            .unwrap()
+            .get(source)
+            .filter(|cached| cached.fingerprint == fingerprint)
+            .map(|cached| cached.elements.clone());
+        if let Some(elements) = cached {
+            debug!("Index cache hit: {source}");
+            return Ok(elements);
+        }
+
+        debug!("Index cache miss: {source}");
+        let elements = build()?;
+        self.sources.lock().unwrap().insert(
+            source.to_string(),
+            CachedSource {
+                fingerprint,
+                elements: elements.clone(),
+            },
+        );
+        self.dirty.store(true, Ordering::Relaxed);
+        Ok(elements)
+    }
+
+    pub fn save(&self) -> Result<(), std::io::Error> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if !self.dirty.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let file = CacheFile {
+            version: VERSION,
+            sources: self.sources.lock().unwrap().clone(),
+        };
+        // Write to a temporary file first, so concurrent instances never read a partial index
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(&file)?)?;
+        fs::rename(tmp_path, path)
+    }
+}
+
+/// Hashes the modification times of the given paths, together with anything
+/// else the result of a source depends on. Missing paths hash as missing.
+pub fn fingerprint<'a, I, H>(paths: I, extra: &H) -> u64
+where
+    I: IntoIterator<Item = &'a Path>,
+    H: Hash + ?Sized,
+{
+    let mut hasher = DefaultHasher::new();
+    for path in paths {
+        path.hash(&mut hasher);
+        path.metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .hash(&mut hasher);
+    }
+    extra.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn get_or_build_uses_fingerprint() {
+        let cache = IndexCache::default();
+        let element = Element {
+            name: "foo".to_string(),
+            value: "foo".to_string(),
+            ..Element::default()
+        };
+
+        let built = cache
+            .get_or_build("path:/bin", 1, || Ok(vec![element.clone()]))
+            .unwrap();
+        assert_eq!(built, vec![element.clone()]);
+
+        let cached = cache
+            .get_or_build("path:/bin", 1, || panic!("should be cached"))
+            .unwrap();
+        assert_eq!(cached, vec![element]);
+
+        let rebuilt = cache
+            .get_or_build("path:/bin", 2, || Ok(Vec::new()))
+            .unwrap();
+        assert!(rebuilt.is_empty());
+    }
+
+    #[test]
+    fn fingerprint_changes() {
+        let root = TempDir::new("cache");
+        let dir = root.join("source");
+        let missing = fingerprint([dir.as_path()], "");
+        fs::create_dir_all(&dir).unwrap();
+        let created = fingerprint([dir.as_path()], "");
+        let other_extra = fingerprint([dir.as_path()], "de_DE");
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_ne!(missing, created);
+        assert_ne!(created, other_extra);
+        assert_eq!(missing, fingerprint([dir.as_path()], ""));
+    }
+}
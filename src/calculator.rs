@@ -291,6 +291,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn test_format_result() {
         // Integers
         assert_eq!(format_result(5.0), "5");
@@ -19,6 +19,10 @@ pub struct ColorConfig {
     pub text_query: Color,
     pub text_selected: Color,
     pub prompt: Color,
+    /// Placeholder text of an empty query and headers of JSON input
+    pub placeholder: Color,
+    /// Characters of a result that match the query
+    pub text_match: Color,
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -27,11 +31,15 @@ pub struct KeybindingsConfig {
     pub delete: Vec<KeyCombo>,
     pub delete_word: Vec<KeyCombo>,
     pub execute: Vec<KeyCombo>,
+    pub execute_terminal: Vec<KeyCombo>,
     pub paste: Vec<KeyCombo>,
     pub complete: Vec<KeyCombo>,
     pub nav_up: Vec<KeyCombo>,
     pub nav_down: Vec<KeyCombo>,
     pub exit: Vec<KeyCombo>,
+    pub mark: Vec<KeyCombo>,
+    /// Print the selection and exit with 10 + the position in this list
+    pub custom: Vec<Vec<KeyCombo>>,
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -40,10 +48,38 @@ pub struct HistoryConfig {
     pub decrease_interval: u64,
 }
 
-#[derive(Deserialize, Clone, Debug, Default)]
+#[derive(Deserialize, Clone, Debug)]
 #[serde(default)]
 pub struct SearchConfig {
     pub show_hidden_files: bool,
+    /// Always match case insensitive, instead of only for lowercase queries
+    pub ignore_case: bool,
+    pub name_weight: f64,
+    pub generic_name_weight: f64,
+    pub keywords_weight: f64,
+    pub comment_weight: f64,
+    pub categories_weight: f64,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct IconConfig {
+    pub enabled: bool,
+    pub theme: String,
+    pub size: u32,
+}
+
+/// What happens if kickoff is started again while its window is open.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SingleInstance {
+    /// Close the open window
+    #[default]
+    Toggle,
+    /// Keep the open window and exit
+    Ignore,
+    /// Close the open window and show a fresh one instead
+    Replace,
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -58,6 +94,12 @@ pub struct Config {
     pub history: HistoryConfig,
     pub keybindings: KeybindingsConfig,
     pub search: SearchConfig,
+    pub icons: IconConfig,
+    /// Overrides the locale used for desktop entries, e.g. `de_DE`
+    pub locale: Option<String>,
+    /// Command prefix used to run programs in a terminal, e.g. `alacritty -e`
+    pub terminal: Option<String>,
+    pub single_instance: SingleInstance,
 }
 
 impl Default for KeybindingsConfig {
@@ -98,6 +140,24 @@ impl Default for KeybindingsConfig {
                 KeyCombo::new(Modifiers::default(), Keysym::Return),
                 KeyCombo::new(Modifiers::default(), Keysym::KP_Enter),
             ],
+            execute_terminal: vec![
+                KeyCombo::new(
+                    ModifiersState {
+                        shift: true,
+                        ..ModifiersState::default()
+                    }
+                    .into(),
+                    Keysym::Return,
+                ),
+                KeyCombo::new(
+                    ModifiersState {
+                        shift: true,
+                        ..ModifiersState::default()
+                    }
+                    .into(),
+                    Keysym::KP_Enter,
+                ),
+            ],
             paste: vec![KeyCombo::new(
                 ModifiersState {
                     ctrl: true,
@@ -116,6 +176,15 @@ impl Default for KeybindingsConfig {
                 KeyCombo::new(Modifiers::default(), Keysym::KP_Down),
             ],
             exit: vec![KeyCombo::new(Modifiers::default(), Keysym::Escape)],
+            mark: vec![KeyCombo::new(
+                ModifiersState {
+                    ctrl: true,
+                    ..ModifiersState::default()
+                }
+                .into(),
+                Keysym::space,
+            )],
+            custom: Vec::new(),
         }
     }
 }
@@ -127,6 +196,8 @@ impl Default for ColorConfig {
             text: Color(255, 255, 255, 255),
             text_query: Color(229, 192, 123, 255),
             text_selected: Color(97, 175, 239, 255),
+            placeholder: Color(92, 99, 112, 255),
+            text_match: Color(152, 195, 121, 255),
         }
     }
 }
@@ -142,6 +213,32 @@ impl Default for Config {
             history: HistoryConfig::default(),
             keybindings: KeybindingsConfig::default(),
             search: SearchConfig::default(),
+            icons: IconConfig::default(),
+            locale: None,
+            terminal: None,
+            single_instance: SingleInstance::default(),
+        }
+    }
+}
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            show_hidden_files: false,
+            ignore_case: false,
+            name_weight: 1.0,
+            generic_name_weight: 0.7,
+            keywords_weight: 0.6,
+            comment_weight: 0.3,
+            categories_weight: 0.3,
+        }
+    }
+}
+impl Default for IconConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            theme: "hicolor".to_string(),
+            size: 32,
         }
     }
 }
@@ -0,0 +1,487 @@
+use crate::app::App;
+use crate::config::SingleInstance;
+use crate::desktop;
+use crate::selection::ElementListBuilder;
+use crate::{gui, load_config, load_entries, load_font, Args};
+use anyhow::Result;
+use log::{debug, error, warn};
+use nix::fcntl::{Flock, FlockArg};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc;
+use tokio::task::{spawn_blocking, JoinHandle};
+use xdg::BaseDirectories;
+
+/// How long to wait for more changes of the sources before rescanning them.
+const RELOAD_DELAY: Duration = Duration::from_millis(500);
+/// How long to wait for a replaced window to close.
+const REPLACE_TIMEOUT: Duration = Duration::from_secs(2);
+const REPLACE_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+const SOCKET_FILE: &str = "kickoff.sock";
+/// Locked by the instance owning the socket, contains its pid.
+const LOCK_FILE: &str = "kickoff.lock";
+
+/// Messages sent by a new instance to the one owning the socket, one per line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+    /// Open the window of a daemon
+    Show,
+    /// Only ask if a window is open
+    Status,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Response {
+    /// The daemon opened its window
+    Shown,
+    /// A window is already open
+    Busy,
+    /// A daemon is running, but has no open window
+    Idle,
+    /// The open window was closed
+    Closed,
+    /// The open window is closing to make room for a new one
+    Replaced,
+}
+
+impl Request {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Show => "show",
+            Self::Status => "status",
+        }
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        match line.trim() {
+            "show" => Some(Self::Show),
+            "status" => Some(Self::Status),
+            _ => None,
+        }
+    }
+}
+
+impl Response {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Shown => "shown",
+            Self::Busy => "busy",
+            Self::Idle => "idle",
+            Self::Closed => "closed",
+            Self::Replaced => "replaced",
+        }
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        match line.trim() {
+            "shown" => Some(Self::Shown),
+            "busy" => Some(Self::Busy),
+            "idle" => Some(Self::Idle),
+            "closed" => Some(Self::Closed),
+            "replaced" => Some(Self::Replaced),
+            _ => None,
+        }
+    }
+}
+
+fn runtime_dir() -> std::io::Result<PathBuf> {
+    BaseDirectories::with_prefix("kickoff").create_runtime_directory("")
+}
+
+fn socket_path() -> std::io::Result<PathBuf> {
+    Ok(runtime_dir()?.join(SOCKET_FILE))
+}
+
+/// Connects to the instance owning the socket, if there is one.
+pub async fn connect() -> Option<UnixStream> {
+    let path = socket_path().ok()?;
+    UnixStream::connect(path).await.ok()
+}
+
+pub async fn request(stream: &mut UnixStream, request: Request) -> Result<Response> {
+    stream
+        .write_all(format!("{}\n", request.as_str()).as_bytes())
+        .await?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).await?;
+    Response::parse(&line).ok_or_else(|| anyhow::anyhow!("Invalid response: {line:?}"))
+}
+
+/// Sends a request to the running instance, if there is one. A replaced window
+/// takes a moment to close, so the request is repeated until it is gone.
+pub async fn ask(request: Request) -> Result<Option<Response>> {
+    let start = Instant::now();
+    while start.elapsed() < REPLACE_TIMEOUT {
+        let Some(mut stream) = connect().await else {
+            return Ok(None);
+        };
+        match self::request(&mut stream, request).await? {
+            Response::Replaced => tokio::time::sleep(REPLACE_POLL_INTERVAL).await,
+            response => return Ok(Some(response)),
+        }
+    }
+    Err(anyhow::anyhow!(
+        "Timed out waiting for the running instance to close"
+    ))
+}
+
+/// State shared between the socket server and the window it guards.
+#[derive(Default)]
+pub struct Session {
+    visible: AtomicBool,
+    /// Set when another invocation wants the window to close
+    pub close: AtomicBool,
+}
+
+impl Session {
+    /// A session for an instance which shows its window right away.
+    pub const fn visible() -> Self {
+        Self {
+            visible: AtomicBool::new(true),
+            close: AtomicBool::new(false),
+        }
+    }
+}
+
+/// The listening socket, removed again once the owning instance exits.
+///
+/// Only the holder of the lock file may create the socket. The lock is released
+/// by the kernel even if kickoff crashes, so there are no stale locks.
+pub struct Server {
+    listener: UnixListener,
+    path: PathBuf,
+    lock: Flock<File>,
+}
+
+/// Keeps answering requests until dropped.
+pub struct ServerHandle {
+    task: JoinHandle<()>,
+    path: PathBuf,
+    _lock: Flock<File>,
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        // The lock is released after this, the socket has to be gone by then
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            debug!("Failed to remove socket: {e}");
+        }
+        self.task.abort();
+    }
+}
+
+impl Server {
+    /// Takes the lock and creates the socket. Fails with `WouldBlock` if another
+    /// instance holds the lock, which then is about to accept connections.
+    pub fn bind() -> std::io::Result<Self> {
+        Self::bind_in(&runtime_dir()?)
+    }
+
+    fn bind_in(dir: &Path) -> std::io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(dir.join(LOCK_FILE))?;
+        let mut lock = Flock::lock(file, FlockArg::LockExclusiveNonblock)
+            .map_err(|(_, errno)| std::io::Error::from(errno))?;
+        lock.set_len(0)?;
+        write!(lock, "{}", std::process::id())?;
+
+        // Holding the lock, any existing socket was left behind by a crashed instance
+        let path = dir.join(SOCKET_FILE);
+        if path.exists() {
+            debug!("Removing stale socket {}", path.display());
+            std::fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        Ok(Self {
+            listener,
+            path,
+            lock,
+        })
+    }
+
+    /// Answers requests while a normal instance shows its window.
+    pub fn spawn_standalone(self, session: Arc<Session>, behavior: SingleInstance) -> ServerHandle {
+        self.spawn(session, behavior, None)
+    }
+
+    fn spawn(
+        self,
+        session: Arc<Session>,
+        behavior: SingleInstance,
+        show: Option<mpsc::Sender<()>>,
+    ) -> ServerHandle {
+        let Self {
+            listener,
+            path,
+            lock,
+        } = self;
+        let task = tokio::spawn(async move {
+            loop {
+                let mut stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        error!("{e}");
+                        continue;
+                    }
+                };
+                let session = session.clone();
+                let show = show.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle(&mut stream, &session, behavior, show.as_ref()).await {
+                        debug!("Failed to handle request: {e}");
+                    }
+                });
+            }
+        });
+        ServerHandle {
+            task,
+            path,
+            _lock: lock,
+        }
+    }
+}
+
+async fn handle(
+    stream: &mut UnixStream,
+    session: &Session,
+    behavior: SingleInstance,
+    show: Option<&mpsc::Sender<()>>,
+) -> Result<()> {
+    let mut line = String::new();
+    BufReader::new(&mut *stream).read_line(&mut line).await?;
+    let request = Request::parse(&line);
+    debug!("Received request {request:?}");
+
+    let visible = session.visible.load(Ordering::SeqCst);
+    let response = match (request, show) {
+        // Only asking for the window toggles or replaces it, probes of scripted
+        // instances must never close what the user has open
+        (Some(Request::Show), _) if visible => match behavior {
+            SingleInstance::Ignore => Response::Busy,
+            SingleInstance::Toggle => {
+                session.close.store(true, Ordering::SeqCst);
+                Response::Closed
+            }
+            SingleInstance::Replace => {
+                session.close.store(true, Ordering::SeqCst);
+                Response::Replaced
+            }
+        },
+        _ if visible => Response::Busy,
+        (Some(Request::Show), Some(show)) => {
+            session.close.store(false, Ordering::SeqCst);
+            session.visible.store(true, Ordering::SeqCst);
+            show.send(()).await?;
+            Response::Shown
+        }
+        _ => Response::Idle,
+    };
+    stream
+        .write_all(format!("{}\n", response.as_str()).as_bytes())
+        .await?;
+    Ok(())
+}
+
+/// Loads everything once and shows the window whenever another instance asks for it.
+pub async fn run(args: Args, server: Server) -> Result<()> {
+    let config = load_config(&args);
+    let font = load_font(&config).await?;
+    let (entries, history) = load_entries(&args, &config).await?;
+    let mut app = App::new(args, config, entries, font, history);
+
+    let session = Arc::new(Session::default());
+    let (show_tx, mut show_rx) = mpsc::channel(1);
+    let _server = server.spawn(session.clone(), app.config.single_instance, Some(show_tx));
+    let (changes_tx, mut changes) = mpsc::channel(1);
+    let _watcher = watch_sources(changes_tx)
+        .map_err(|e| warn!("Failed to watch sources, changes show up after the next launch: {e}"))
+        .ok();
+    debug!("Daemon ready");
+
+    loop {
+        tokio::select! {
+            request = show_rx.recv() => {
+                if request.is_none() {
+                    break;
+                }
+                let window_session = session.clone();
+                app = spawn_blocking(move || gui::run(app, &window_session.close, None)).await?;
+                session.visible.store(false, Ordering::SeqCst);
+            }
+            Some(()) = changes.recv() => {
+                // Installing an application touches many files, let it settle first
+                tokio::time::sleep(RELOAD_DELAY).await;
+                while changes.try_recv().is_ok() {}
+                debug!("Sources changed");
+            }
+        }
+
+        reap_children();
+        // Pick up changed sources and the history written by the last launch
+        match load_entries(&app.args, &app.config).await {
+            Ok((entries, history)) => app.reset(entries, history),
+            Err(e) => warn!("Failed to refresh entries: {e}"),
+        }
+    }
+
+    Ok(())
+}
+
+/// Watches the directories the entries are built from, `$PATH` and the
+/// `applications` directories, and signals `changes` whenever one of them changes.
+fn watch_sources(changes: mpsc::Sender<()>) -> notify::Result<RecommendedWatcher> {
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        match event {
+            Ok(event) => {
+                if matches!(
+                    event.kind,
+                    EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+                ) {
+                    // A reload is pending already if the channel is full
+                    let _ = changes.try_send(());
+                }
+            }
+            Err(e) => debug!("Failed to watch sources: {e}"),
+        }
+    })?;
+
+    let path_var = env::var_os("PATH").unwrap_or_default();
+    let dirs = env::split_paths(&path_var)
+        .map(|dir| (dir, RecursiveMode::NonRecursive))
+        .chain(
+            desktop::data_dirs()
+                .into_iter()
+                .map(|dir| (dir.join("applications"), RecursiveMode::Recursive)),
+        )
+        .chain(
+            ElementListBuilder::flatpak_installations()
+                .into_iter()
+                .map(|dir| (dir, RecursiveMode::NonRecursive)),
+        );
+    for (dir, mode) in dirs {
+        // Directories created later are only picked up when the daemon restarts
+        if !dir.is_dir() {
+            continue;
+        }
+        if let Err(e) = watcher.watch(&dir, mode) {
+            debug!("Failed to watch {}: {e}", dir.display());
+        }
+    }
+    Ok(watcher)
+}
+
+/// Launched programs are children of the daemon and have to be collected once they exit.
+fn reap_children() {
+    while let Ok(status) = waitpid(Pid::from_raw(-1), Some(WaitPidFlag::WNOHANG)) {
+        if status == WaitStatus::StillAlive {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn protocol_roundtrip() {
+        for request in [Request::Show, Request::Status] {
+            assert_eq!(
+                Request::parse(&format!("{}\n", request.as_str())),
+                Some(request)
+            );
+        }
+        for response in [
+            Response::Shown,
+            Response::Busy,
+            Response::Idle,
+            Response::Closed,
+            Response::Replaced,
+        ] {
+            assert_eq!(Response::parse(response.as_str()), Some(response));
+        }
+        assert_eq!(Request::parse("restart"), None);
+    }
+
+    async fn respond(session: &Session, behavior: SingleInstance, request: Request) -> Response {
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+        let (response, handled) = tokio::join!(
+            self::request(&mut client, request),
+            handle(&mut server, session, behavior, None)
+        );
+        handled.unwrap();
+        response.unwrap()
+    }
+
+    #[tokio::test]
+    async fn open_window_behavior() {
+        let session = Session::visible();
+        let response = respond(&session, SingleInstance::Ignore, Request::Show).await;
+        assert_eq!(response, Response::Busy);
+        assert!(!session.close.load(Ordering::SeqCst));
+
+        let response = respond(&session, SingleInstance::Toggle, Request::Show).await;
+        assert_eq!(response, Response::Closed);
+        assert!(session.close.load(Ordering::SeqCst));
+
+        let session = Session::visible();
+        let response = respond(&session, SingleInstance::Replace, Request::Show).await;
+        assert_eq!(response, Response::Replaced);
+        assert!(session.close.load(Ordering::SeqCst));
+
+        // Scripted instances only probe, whatever the behavior is
+        for behavior in [SingleInstance::Toggle, SingleInstance::Replace] {
+            let session = Session::visible();
+            let response = respond(&session, behavior, Request::Status).await;
+            assert_eq!(response, Response::Busy);
+            assert!(!session.close.load(Ordering::SeqCst));
+        }
+
+        let idle = Session::default();
+        let response = respond(&idle, SingleInstance::Toggle, Request::Status).await;
+        assert_eq!(response, Response::Idle);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_instances_bind_once() {
+        let dir = TempDir::new("lock");
+
+        let attempts: Vec<_> = (0..8)
+            .map(|_| {
+                let dir = dir.to_path_buf();
+                tokio::spawn(async move { Server::bind_in(&dir) })
+            })
+            .collect();
+        let mut servers = Vec::new();
+        for attempt in attempts {
+            match attempt.await.unwrap() {
+                Ok(server) => servers.push(server),
+                Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::WouldBlock),
+            }
+        }
+        assert_eq!(servers.len(), 1);
+        let pid = std::fs::read_to_string(dir.join(LOCK_FILE)).unwrap();
+        assert_eq!(pid, std::process::id().to_string());
+
+        // Dropping the lock without cleaning up, like a crash, leaves a stale socket behind
+        drop(servers);
+        assert!(dir.join(SOCKET_FILE).exists());
+        let server = Server::bind_in(&dir).unwrap();
+        assert!(UnixStream::connect(&server.path).await.is_ok());
+    }
+}
@@ -0,0 +1,656 @@
+use std::collections::{HashMap, HashSet};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::{fmt, fs};
+use xdg::BaseDirectories;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ExecError {
+    UnterminatedQuote,
+    TrailingEscape,
+    UnknownFieldCode(char),
+    DanglingPercent,
+    Empty,
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnterminatedQuote => write!(f, "unterminated quote in Exec key"),
+            Self::TrailingEscape => write!(f, "trailing backslash in Exec key"),
+            Self::UnknownFieldCode(c) => write!(f, "unknown field code %{c} in Exec key"),
+            Self::DanglingPercent => write!(f, "dangling % at the end of Exec key"),
+            Self::Empty => write!(f, "empty Exec key"),
+        }
+    }
+}
+
+impl std::error::Error for ExecError {}
+
+/// Values needed to expand the field codes of an Exec key.
+#[derive(Debug, Default)]
+pub struct FieldCodes<'a> {
+    /// Translated `Name` of the entry, used for `%c`
+    pub name: &'a str,
+    /// `Icon` key of the entry, used for `%i`
+    pub icon: Option<&'a str>,
+    /// Location of the desktop file, used for `%k`
+    pub location: Option<&'a Path>,
+}
+
+/// The groups of a desktop file with their raw, still escaped, key value pairs.
+#[derive(Debug, Default)]
+pub struct DesktopFile {
+    groups: Vec<(String, HashMap<String, String>)>,
+}
+
+impl DesktopFile {
+    pub fn parse(content: &str) -> Self {
+        let mut res = Self::default();
+
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                res.groups
+                    .push((line[1..line.len() - 1].to_string(), HashMap::new()));
+            } else if let Some((key, value)) = line.split_once('=') {
+                if let Some((_, entries)) = res.groups.last_mut() {
+                    // The first occurrence of a key wins
+                    entries
+                        .entry(key.trim().to_string())
+                        .or_insert_with(|| value.trim().to_string());
+                }
+            }
+        }
+
+        res
+    }
+
+    pub fn group(&self, name: &str) -> Option<&HashMap<String, String>> {
+        self.groups
+            .iter()
+            .find(|(group, _)| group == name)
+            .map(|(_, entries)| entries)
+    }
+}
+
+/// A POSIX locale of the form `lang_COUNTRY.ENCODING@MODIFIER`,
+/// the encoding is irrelevant for desktop files and therefore dropped.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Locale {
+    lang: String,
+    country: Option<String>,
+    modifier: Option<String>,
+}
+
+impl Locale {
+    pub fn parse(locale: &str) -> Option<Self> {
+        let (rest, modifier) = match locale.split_once('@') {
+            Some((rest, modifier)) => (rest, Some(modifier.to_string())),
+            None => (locale, None),
+        };
+        let rest = rest.split_once('.').map_or(rest, |(rest, _encoding)| rest);
+        let (lang, country) = match rest.split_once('_') {
+            Some((lang, country)) => (lang, Some(country.to_string())),
+            None => (rest, None),
+        };
+
+        if lang.is_empty() || lang == "C" || lang == "POSIX" {
+            return None;
+        }
+
+        Some(Self {
+            lang: lang.to_string(),
+            country,
+            modifier,
+        })
+    }
+
+    /// Determines the message locale the same way gettext does.
+    pub fn from_env() -> Option<Self> {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|value| Self::parse(&value))
+    }
+
+    /// Keys to try for a localized value, most specific first.
+    fn candidates(&self, key: &str) -> Vec<String> {
+        let mut res = Vec::new();
+        if let (Some(country), Some(modifier)) = (&self.country, &self.modifier) {
+            res.push(format!("{key}[{}_{country}@{modifier}]", self.lang));
+        }
+        if let Some(country) = &self.country {
+            res.push(format!("{key}[{}_{country}]", self.lang));
+        }
+        if let Some(modifier) = &self.modifier {
+            res.push(format!("{key}[{}@{modifier}]", self.lang));
+        }
+        res.push(format!("{key}[{}]", self.lang));
+        res
+    }
+}
+
+/// The parts of the session that decide how desktop entries are presented.
+#[derive(Debug, Clone, Default, Hash)]
+pub struct Environment {
+    pub locale: Option<Locale>,
+    /// Entries of `XDG_CURRENT_DESKTOP`, e.g. `sway` or `GNOME`
+    pub current_desktops: Vec<String>,
+}
+
+impl Environment {
+    /// Reads the session from the environment, `locale` overrides the message locale.
+    pub fn from_env(locale: Option<&str>) -> Self {
+        Self {
+            locale: locale.map_or_else(Locale::from_env, Locale::parse),
+            current_desktops: std::env::var("XDG_CURRENT_DESKTOP")
+                .map(|desktops| {
+                    desktops
+                        .split(':')
+                        .filter(|desktop| !desktop.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Evaluates the `OnlyShowIn` and `NotShowIn` keys of an entry.
+    pub fn shows(&self, only_show_in: Option<&str>, not_show_in: Option<&str>) -> bool {
+        let listed = |list: &str| {
+            parse_list(list)
+                .iter()
+                .any(|desktop| self.current_desktops.contains(desktop))
+        };
+        only_show_in.map_or(true, listed) && !not_show_in.is_some_and(listed)
+    }
+}
+
+/// Checks if a program exists, either as an absolute path or inside `$PATH`.
+pub fn executable_exists(program: &str) -> bool {
+    let is_executable = |path: &Path| {
+        path.metadata()
+            .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+    };
+    if program.contains('/') {
+        return is_executable(Path::new(program));
+    }
+    std::env::var_os("PATH").is_some_and(|path| {
+        std::env::split_paths(&path).any(|dir| is_executable(&dir.join(program)))
+    })
+}
+
+/// Looks up a localestring key, falling back to the unlocalized key.
+pub fn localized<'a>(
+    group: &'a HashMap<String, String>,
+    key: &str,
+    locale: Option<&Locale>,
+) -> Option<&'a String> {
+    locale
+        .into_iter()
+        .flat_map(|locale| locale.candidates(key))
+        .find_map(|candidate| group.get(&candidate))
+        .or_else(|| group.get(key))
+}
+
+/// Data directories containing an `applications` directory, ordered by precedence.
+/// Flatpak exports are appended in case the session did not add them to `XDG_DATA_DIRS`.
+pub fn data_dirs() -> Vec<PathBuf> {
+    let xdg_dirs = BaseDirectories::new();
+    let mut res: Vec<PathBuf> = xdg_dirs.get_data_home().into_iter().collect();
+    res.append(&mut xdg_dirs.get_data_dirs());
+    if let Some(data_home) = xdg_dirs.get_data_home() {
+        res.push(data_home.join("flatpak/exports/share"));
+    }
+    res.push(PathBuf::from("/var/lib/flatpak/exports/share"));
+
+    let mut seen = HashSet::new();
+    res.retain(|dir| seen.insert(dir.clone()));
+    res
+}
+
+/// Collects all desktop files below the `applications` directory of each data dir,
+/// keyed by their desktop file ID. If multiple files share an ID, only the one from
+/// the most important data dir is returned.
+pub fn find_desktop_files(data_dirs: &[PathBuf]) -> Vec<(String, PathBuf)> {
+    let mut seen = HashSet::new();
+    let mut res = Vec::new();
+    for data_dir in data_dirs {
+        let mut found = Vec::new();
+        collect_desktop_files(&data_dir.join("applications"), "", &mut found);
+        for (id, path) in found {
+            if seen.insert(id.clone()) {
+                res.push((id, path));
+            }
+        }
+    }
+    res
+}
+
+fn collect_desktop_files(dir: &Path, prefix: &str, res: &mut Vec<(String, PathBuf)>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let Some(file_name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        if file_name.ends_with(".desktop") {
+            res.push((format!("{prefix}{file_name}"), entry.path()));
+        } else if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            // Subdirectories become part of the ID, kde/foo.desktop is kde-foo.desktop
+            collect_desktop_files(&entry.path(), &format!("{prefix}{file_name}-"), res);
+        }
+    }
+}
+
+/// Splits a list value like `Actions` or `Categories` at unescaped semicolons.
+pub fn parse_list(value: &str) -> Vec<String> {
+    let mut res = Vec::new();
+    let mut current = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(';') => current.push(';'),
+                Some(other) => {
+                    current.push('\\');
+                    current.push(other);
+                }
+                None => current.push('\\'),
+            },
+            ';' => res.push(unescape_value(&std::mem::take(&mut current))),
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        res.push(unescape_value(&current));
+    }
+    res.retain(|entry| !entry.is_empty());
+    res
+}
+
+/// Resolves the escape sequences that apply to every string value
+/// in a desktop file (`\s`, `\n`, `\t`, `\r` and `\\`).
+pub fn unescape_value(value: &str) -> String {
+    let mut res = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => res.push(' '),
+            Some('n') => res.push('\n'),
+            Some('t') => res.push('\t'),
+            Some('r') => res.push('\r'),
+            Some('\\') => res.push('\\'),
+            Some(other) => {
+                // Not a value escape, keep it for the Exec quoting rules
+                res.push('\\');
+                res.push(other);
+            }
+            None => res.push('\\'),
+        }
+    }
+    res
+}
+
+/// Splits an already unescaped Exec value into its arguments, following the
+/// quoting rules of the Desktop Entry Specification. Field codes are kept as is.
+fn tokenize(exec: &str) -> Result<Vec<String>, ExecError> {
+    let mut res = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut in_token = false;
+    let mut chars = exec.chars();
+
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' => quoted = false,
+                '\\' => match chars.next() {
+                    Some(escaped @ ('"' | '`' | '$' | '\\')) => current.push(escaped),
+                    Some(other) => {
+                        current.push('\\');
+                        current.push(other);
+                    }
+                    None => return Err(ExecError::TrailingEscape),
+                },
+                c => current.push(c),
+            }
+            continue;
+        }
+
+        match c {
+            ' ' | '\t' | '\n' => {
+                if in_token {
+                    res.push(std::mem::take(&mut current));
+                    in_token = false;
+                }
+            }
+            '"' => {
+                quoted = true;
+                in_token = true;
+            }
+            '\\' => {
+                // Not allowed by the spec outside of quotes, but commonly found in the wild
+                match chars.next() {
+                    Some(escaped) => current.push(escaped),
+                    None => return Err(ExecError::TrailingEscape),
+                }
+                in_token = true;
+            }
+            c => {
+                current.push(c);
+                in_token = true;
+            }
+        }
+    }
+
+    if quoted {
+        return Err(ExecError::UnterminatedQuote);
+    }
+    if in_token {
+        res.push(current);
+    }
+
+    Ok(res)
+}
+
+/// Turns the raw value of an Exec key into an argument vector, ready to be
+/// executed without a shell. File and URL field codes expand to nothing since
+/// kickoff never passes files to the launched application.
+pub fn parse_exec(exec: &str, codes: &FieldCodes) -> Result<Vec<String>, ExecError> {
+    let mut argv = Vec::new();
+
+    for token in tokenize(&unescape_value(exec))? {
+        match token.as_str() {
+            "%f" | "%F" | "%u" | "%U" | "%d" | "%D" | "%n" | "%N" | "%v" | "%m" => continue,
+            "%i" => {
+                if let Some(icon) = codes.icon {
+                    argv.push("--icon".to_string());
+                    argv.push(icon.to_string());
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        let mut arg = String::with_capacity(token.len());
+        let mut chars = token.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                arg.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('%') => arg.push('%'),
+                Some('c') => arg.push_str(codes.name),
+                Some('k') => {
+                    if let Some(location) = codes.location {
+                        arg.push_str(&location.to_string_lossy());
+                    }
+                }
+                Some('i') => {
+                    if let Some(icon) = codes.icon {
+                        arg.push_str(icon);
+                    }
+                }
+                Some('f' | 'F' | 'u' | 'U' | 'd' | 'D' | 'n' | 'N' | 'v' | 'm') => {}
+                Some(other) => return Err(ExecError::UnknownFieldCode(other)),
+                None => return Err(ExecError::DanglingPercent),
+            }
+        }
+        argv.push(arg);
+    }
+
+    if argv.is_empty() {
+        Err(ExecError::Empty)
+    } else {
+        Ok(argv)
+    }
+}
+
+/// Joins an argument vector into a single string that `sh` parses back into
+/// the same arguments. Used to keep a readable and re-executable `value`.
+pub fn shell_join(argv: &[String]) -> String {
+    argv.iter()
+        .map(|arg| shell_quote(arg))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn shell_quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c))
+    {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn parse(exec: &str) -> Result<Vec<String>, ExecError> {
+        parse_exec(
+            exec,
+            &FieldCodes {
+                name: "Some App",
+                icon: Some("some-icon"),
+                location: None,
+            },
+        )
+    }
+
+    #[test]
+    fn parse_exec_simple() {
+        assert_eq!(parse("firefox %u").unwrap(), vec!["firefox"]);
+        assert_eq!(
+            parse("/usr/lib/firefox/firefox --new-window %u").unwrap(),
+            vec!["/usr/lib/firefox/firefox", "--new-window"]
+        );
+        assert_eq!(
+            parse("code  --unity-launch   %F").unwrap(),
+            vec!["code", "--unity-launch"]
+        );
+    }
+
+    #[test]
+    fn parse_exec_quoting() {
+        assert_eq!(
+            parse(r#"/usr/lib/firefox-developer-edition/firefox --class="firefoxdeveloperedition" --new-window %u"#)
+                .unwrap(),
+            vec![
+                "/usr/lib/firefox-developer-edition/firefox",
+                "--class=firefoxdeveloperedition",
+                "--new-window"
+            ]
+        );
+        assert_eq!(
+            parse(r#""/opt/My Program/bin/program" --flag"#).unwrap(),
+            vec!["/opt/My Program/bin/program", "--flag"]
+        );
+        // Escaped quote inside a quoted argument, written with a string level escape
+        assert_eq!(
+            parse(r#"sh -c "echo \\"hello world\\" \\$HOME""#).unwrap(),
+            vec!["sh", "-c", r#"echo "hello world" $HOME"#]
+        );
+        assert_eq!(parse(r#"echo """#).unwrap(), vec!["echo", ""]);
+        assert_eq!(
+            parse(r#"echo "unterminated"#),
+            Err(ExecError::UnterminatedQuote)
+        );
+    }
+
+    #[test]
+    fn parse_exec_field_codes() {
+        assert_eq!(parse("app 100%%").unwrap(), vec!["app", "100%"]);
+        assert_eq!(
+            parse("app %i --title=%c").unwrap(),
+            vec!["app", "--icon", "some-icon", "--title=Some App"]
+        );
+        assert_eq!(
+            parse_exec(
+                "app %i %k",
+                &FieldCodes {
+                    name: "Some App",
+                    icon: None,
+                    location: Some(&PathBuf::from("/usr/share/applications/app.desktop")),
+                },
+            )
+            .unwrap(),
+            vec!["app", "/usr/share/applications/app.desktop"]
+        );
+        assert_eq!(parse("app %x"), Err(ExecError::UnknownFieldCode('x')));
+        assert_eq!(parse("app 100%"), Err(ExecError::DanglingPercent));
+        assert_eq!(parse("%U"), Err(ExecError::Empty));
+    }
+
+    #[test]
+    fn parse_exec_real_world() {
+        // Steam game shortcut
+        assert_eq!(
+            parse("steam steam://rungameid/1091500").unwrap(),
+            vec!["steam", "steam://rungameid/1091500"]
+        );
+        // Flatpak export
+        assert_eq!(
+            parse("/usr/bin/flatpak run --branch=stable --arch=x86_64 --command=obsidian --file-forwarding md.obsidian.Obsidian @@u %U @@").unwrap(),
+            vec![
+                "/usr/bin/flatpak",
+                "run",
+                "--branch=stable",
+                "--arch=x86_64",
+                "--command=obsidian",
+                "--file-forwarding",
+                "md.obsidian.Obsidian",
+                "@@u",
+                "@@"
+            ]
+        );
+        // Wine shortcut with escaped spaces and backslashes
+        assert_eq!(
+            parse(
+                r#"env WINEPREFIX="/home/user/.wine" wine C:\\\\Program\\ Files\\\\App\\\\app.exe"#
+            )
+            .unwrap(),
+            vec![
+                "env",
+                "WINEPREFIX=/home/user/.wine",
+                "wine",
+                r"C:\Program Files\App\app.exe"
+            ]
+        );
+        // Shell wrapper from a Jetbrains launcher
+        assert_eq!(
+            parse(r#"bash -c "\\"$HOME/.local/share/JetBrains/Toolbox/bin/jetbrains-toolbox\\" --minimize""#).unwrap(),
+            vec![
+                "bash",
+                "-c",
+                r#""$HOME/.local/share/JetBrains/Toolbox/bin/jetbrains-toolbox" --minimize"#
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_list_escapes() {
+        assert_eq!(parse_list("a;b;c;"), vec!["a", "b", "c"]);
+        assert_eq!(parse_list(r"semi\;colon;;x\sy"), vec!["semi;colon", "x y"]);
+        assert!(parse_list("").is_empty());
+    }
+
+    #[test]
+    fn locale_matching() {
+        let group: HashMap<String, String> = [
+            ("Name", "Default"),
+            ("Name[sr]", "sr"),
+            ("Name[sr@Latn]", "sr@Latn"),
+            ("Name[sr_YU]", "sr_YU"),
+            ("Name[de]", "de"),
+        ]
+        .iter()
+        .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+        .collect();
+        let name = |locale: &str| {
+            localized(&group, "Name", Locale::parse(locale).as_ref())
+                .unwrap()
+                .as_str()
+        };
+
+        assert_eq!(name("sr_YU.UTF-8@Latn"), "sr_YU");
+        assert_eq!(name("sr_CS@Latn"), "sr@Latn");
+        assert_eq!(name("sr_CS"), "sr");
+        assert_eq!(name("de_DE.UTF-8"), "de");
+        assert_eq!(name("fr_FR"), "Default");
+        assert_eq!(name("C.UTF-8"), "Default");
+        assert_eq!(name(""), "Default");
+    }
+
+    #[test]
+    fn show_in_desktops() {
+        let env = Environment {
+            locale: None,
+            current_desktops: vec!["sway".to_string(), "wlroots".to_string()],
+        };
+        assert!(env.shows(None, None));
+        assert!(env.shows(Some("GNOME;wlroots;"), None));
+        assert!(!env.shows(Some("GNOME;KDE;"), None));
+        assert!(env.shows(None, Some("GNOME;")));
+        assert!(!env.shows(None, Some("sway;")));
+        assert!(!Environment::default().shows(Some("GNOME;"), None));
+    }
+
+    #[test]
+    fn find_desktop_files_shadowing() {
+        let root = TempDir::new("desktop");
+        let user = root.join("user");
+        let system = root.join("system");
+        fs::create_dir_all(user.join("applications")).unwrap();
+        fs::create_dir_all(system.join("applications/kde")).unwrap();
+        fs::write(user.join("applications/foo.desktop"), "").unwrap();
+        fs::write(system.join("applications/foo.desktop"), "").unwrap();
+        fs::write(system.join("applications/kde/foo.desktop"), "").unwrap();
+        fs::write(system.join("applications/README"), "").unwrap();
+
+        let mut files = find_desktop_files(&[user.clone(), system.clone()]);
+        files.sort();
+
+        assert_eq!(
+            files,
+            vec![
+                (
+                    "foo.desktop".to_string(),
+                    user.join("applications/foo.desktop")
+                ),
+                (
+                    "kde-foo.desktop".to_string(),
+                    system.join("applications/kde/foo.desktop")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn shell_join_roundtrip() {
+        assert_eq!(shell_join(&["firefox".to_string()]), "firefox");
+        assert_eq!(
+            shell_join(&["sh".to_string(), "-c".to_string(), "echo 'hi'".to_string()]),
+            r#"sh -c 'echo '\''hi'\'''"#
+        );
+        assert_eq!(shell_join(&[String::new()]), "''");
+    }
+}
@@ -0,0 +1,88 @@
+use crate::app::{calculator_element, print_elements};
+use crate::config::SearchConfig;
+use crate::selection::{self, Element, ElementList};
+use crate::{calculator, load_config, load_entries, Args};
+use anyhow::Result;
+use std::io::Write;
+
+/// Prints the ranked results of `query` without opening a window, for `--filter`.
+pub async fn run(args: Args, query: &str) -> Result<()> {
+    let config = load_config(&args);
+    let (mut entries, history) = load_entries(&args, &config).await?;
+    if args.from_stdin {
+        let mut elements = Vec::new();
+        selection::stream_stdin(args.format, args.read0, |batch| {
+            elements.extend(batch);
+            true
+        })
+        .await?;
+        entries.extend(elements, history.as_ref());
+    }
+
+    let results = rank(&entries, query, &config.search, args.calculator());
+    let terminator = if args.print0 { "\0" } else { "\n" };
+    print_elements(&results, args.output, terminator);
+    std::io::stdout().flush()?;
+    Ok(())
+}
+
+/// Matches of `query`, best first, like they are listed in the window.
+/// A calculator result comes before every entry, headers are left out.
+pub fn rank(
+    entries: &ElementList,
+    query: &str,
+    config: &SearchConfig,
+    calculator: bool,
+) -> Vec<Element> {
+    let mut results = Vec::new();
+    if calculator && calculator::is_math_expression(query) {
+        if let Ok(result) = calculator::evaluate(query) {
+            results.push(calculator_element(query, result));
+        }
+    }
+
+    let all_entries = entries.as_ref_vec();
+    results.extend(
+        entries
+            .search(query, config)
+            .into_iter()
+            .map(|index| all_entries[index])
+            .filter(|element| !element.header)
+            .cloned(),
+    );
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rank_entries() {
+        let element = |name: &str, base_score| Element {
+            name: name.to_string(),
+            value: name.to_string(),
+            base_score,
+            ..Element::default()
+        };
+        let entries = ElementList::from(vec![
+            element("firefox", 0),
+            element("fish", 0),
+            element("fishing", 100),
+        ]);
+        let config = SearchConfig::default();
+        let names = |results: Vec<Element>| {
+            results
+                .into_iter()
+                .map(|element| element.name)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            names(rank(&entries, "fis", &config, true)),
+            vec!["fishing", "fish"]
+        );
+        assert_eq!(names(rank(&entries, "1+2", &config, true)), vec!["1+2 = 3"]);
+        assert!(rank(&entries, "1+2", &config, false).is_empty());
+    }
+}
@@ -14,9 +14,39 @@ use fontconfig::Fontconfig;
 
 use image::{Pixel, RgbaImage};
 
+/// Color of each character of a rendered text.
+pub trait TextColor {
+    fn at(&self, index: usize) -> &Color;
+}
+
+impl TextColor for Color {
+    fn at(&self, _index: usize) -> &Color {
+        self
+    }
+}
+
+/// Draws the characters at `positions` in `highlight`, like the matches of a query.
+pub struct Highlighted<'a> {
+    pub color: &'a Color,
+    pub highlight: &'a Color,
+    /// Sorted character indices
+    pub positions: &'a [usize],
+}
+
+impl TextColor for Highlighted<'_> {
+    fn at(&self, index: usize) -> &Color {
+        if self.positions.binary_search(&index).is_ok() {
+            self.highlight
+        } else {
+            self.color
+        }
+    }
+}
+
 pub struct Font {
     fonts: Vec<fontdue::Font>,
-    layout: RefCell<Layout>,
+    /// Glyphs carry the index of the character they were laid out for
+    layout: RefCell<Layout<usize>>,
     size: f32,
     scale: i32,
     glyph_cache: RefCell<HashMap<GlyphRasterConfig, (Metrics, Vec<u8>)>>,
@@ -58,6 +88,19 @@ impl Font {
         })
     }
 
+    /// A font without any faces, for tests that don't draw.
+    #[cfg(test)]
+    pub fn empty() -> Self {
+        Self {
+            fonts: Vec::new(),
+            layout: RefCell::new(Layout::new(CoordinateSystem::PositiveYDown)),
+            size: 0.,
+            scale: 1,
+            tab_width: 8,
+            glyph_cache: RefCell::new(HashMap::new()),
+        }
+    }
+
     pub fn set_scale(&mut self, scale: i32) {
         self.scale = scale;
     }
@@ -79,39 +122,40 @@ impl Font {
         }
     }
 
-    fn replace_tabs(input: &str, tab_width: usize) -> String {
-        let mut res = String::new();
+    /// Expands tabs to spaces, keeping the index of the character each one came from.
+    fn replace_tabs(input: &str, tab_width: usize) -> Vec<(usize, char)> {
+        let mut res = Vec::new();
         for (idx, c) in input.chars().enumerate() {
             if c == '\t' {
                 let tab_alignment = idx % tab_width;
-                if tab_alignment == 0 {
-                    res.push_str(" ".repeat(8).as_str());
+                let spaces = if tab_alignment == 0 {
+                    8
                 } else {
-                    res.push_str(" ".repeat(tab_width - tab_alignment).as_str());
-                }
+                    tab_width - tab_alignment
+                };
+                res.extend(std::iter::repeat((idx, ' ')).take(spaces));
             } else {
-                res.push(c);
+                res.push((idx, c));
             }
         }
 
         res
     }
 
-    pub fn render(
-        &self,
-        text: &str,
-        color: &Color,
-        image: &mut RgbaImage,
-        x_offset: u32,
-        y_offset: u32,
-        max_width: Option<usize>,
-    ) -> (u32, u32) {
-        let mut width = 0;
-        let mut current_width = 0.;
+    /// Width of the text in pixel, without rendering it.
+    pub fn width(&self, text: &str) -> u32 {
         let mut layout = self.layout.borrow_mut();
+        self.layout_text(&mut layout, text);
+        layout
+            .glyphs()
+            .last()
+            .map_or(0, |glyph| glyph.x as u32 + glyph.width as u32)
+    }
+
+    fn layout_text(&self, layout: &mut Layout<usize>, text: &str) {
         layout.reset(&LayoutSettings::default());
 
-        for c in Self::replace_tabs(text, self.tab_width).chars() {
+        for (index, c) in Self::replace_tabs(text, self.tab_width) {
             let mut font_index = 0;
             for (i, font) in self.fonts.iter().enumerate() {
                 if font.lookup_glyph_index(c) != 0 {
@@ -121,9 +165,29 @@ impl Font {
             }
             layout.append(
                 &self.fonts,
-                &TextStyle::new(&c.to_string(), self.size * self.scale as f32, font_index),
+                &TextStyle::with_user_data(
+                    &c.to_string(),
+                    self.size * self.scale as f32,
+                    font_index,
+                    index,
+                ),
             );
         }
+    }
+
+    pub fn render(
+        &self,
+        text: &str,
+        color: &impl TextColor,
+        image: &mut RgbaImage,
+        x_offset: u32,
+        y_offset: u32,
+        max_width: Option<usize>,
+    ) -> (u32, u32) {
+        let mut width = 0;
+        let mut current_width = 0.;
+        let mut layout = self.layout.borrow_mut();
+        self.layout_text(&mut layout, text);
 
         for glyph in layout.glyphs() {
             if let Some(max_width) = max_width {
@@ -133,6 +197,7 @@ impl Font {
             }
             let (metrics, bitmap) = self.render_glyph(glyph.key);
             current_width += metrics.advance_width;
+            let color = color.at(glyph.user_data);
             for (i, alpha) in bitmap.iter().enumerate() {
                 if alpha != &0 && glyph.width > 0 {
                     let x = glyph.x + x_offset as f32 + (i % glyph.width) as f32;
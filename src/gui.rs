@@ -1,4 +1,4 @@
-use crate::{keybinds::Keybindings, App};
+use crate::{keybinds::Keybindings, selection::Element, App};
 use image::Pixel;
 use log::{debug, error};
 use smithay_client_toolkit::{
@@ -7,7 +7,10 @@ use smithay_client_toolkit::{
     delegate_registry, delegate_seat, delegate_shm,
     output::{OutputHandler, OutputState},
     reexports::{
-        calloop::{EventLoop, LoopHandle},
+        calloop::{
+            channel::{Channel, Event},
+            EventLoop, LoopHandle,
+        },
         calloop_wayland_source::WaylandSource,
     },
     registry::{ProvidesRegistryState, RegistryState},
@@ -28,6 +31,7 @@ use smithay_client_toolkit::{
 };
 use std::{
     io::{BufWriter, Read, Write},
+    sync::atomic::{AtomicBool, Ordering},
     time::Duration,
 };
 use wayland_client::{
@@ -40,6 +44,7 @@ use wl_clipboard_rs::paste::{get_contents, ClipboardType, Error, MimeType, Seat}
 #[derive(Clone)]
 pub enum Action {
     Execute,
+    ExecuteTerminal,
     Exit,
     Complete,
     NavUp,
@@ -47,10 +52,16 @@ pub enum Action {
     Delete,
     DeleteWord,
     Paste,
+    Mark,
     Insert(String),
+    /// Custom keybinding, by its position in the config
+    Custom(u8),
 }
 
-pub fn run(app: App) {
+/// Shows the window until the user executes a selection or exits, then hands the app back.
+///
+/// Elements sent through `incoming` are added to the list while it is shown.
+pub fn run(app: App, close: &AtomicBool, incoming: Option<Channel<Vec<Element>>>) -> App {
     let conn = Connection::connect_to_env().unwrap();
 
     let (globals, event_queue) = registry_queue_init(&conn).unwrap();
@@ -99,6 +110,17 @@ pub fn run(app: App) {
         loop_handle: event_loop.handle(),
     };
 
+    if let Some(incoming) = incoming {
+        gui_layer.app.loading = true;
+        event_loop
+            .handle()
+            .insert_source(incoming, |event, (), gui_layer| match event {
+                Event::Msg(elements) => gui_layer.app.extend(elements),
+                Event::Closed => gui_layer.app.loading = false,
+            })
+            .expect("Failed to insert stdin source");
+    }
+
     loop {
         event_loop
             .dispatch(Duration::from_millis(50), &mut gui_layer)
@@ -110,11 +132,11 @@ pub fn run(app: App) {
             Some(Action::DeleteWord) => gui_layer.app.delete_word(),
             Some(Action::NavUp) => gui_layer.app.nav_up(1),
             Some(Action::NavDown) => gui_layer.app.nav_down(1),
+            Some(Action::Mark) => gui_layer.app.toggle_mark(),
             Some(Action::Insert(s)) => gui_layer.app.insert(s),
-            Some(Action::Execute) => {
-                gui_layer.app.execute();
-                gui_layer.exit = true;
-            }
+            Some(Action::Execute) => gui_layer.exit = gui_layer.app.execute(),
+            Some(Action::ExecuteTerminal) => gui_layer.exit = gui_layer.app.execute_in_terminal(),
+            Some(Action::Custom(index)) => gui_layer.exit = gui_layer.app.execute_custom(*index),
             Some(Action::Paste) => {
                 let result =
                     get_contents(ClipboardType::Regular, Seat::Unspecified, MimeType::Text);
@@ -132,11 +154,22 @@ pub fn run(app: App) {
             _ => {}
         }
 
+        if gui_layer.app.poll_script() {
+            gui_layer.exit = true;
+        }
+
+        if close.load(Ordering::SeqCst) {
+            debug!("closed by another invocation");
+            gui_layer.exit = true;
+        }
+
         if gui_layer.exit {
             debug!("exiting kickoff");
             break;
         }
     }
+
+    gui_layer.app
 }
 
 struct GuiLayer {
@@ -158,7 +191,7 @@ struct GuiLayer {
     app: App,
     next_action: Option<Action>,
     keybindings: Keybindings,
-    loop_handle: LoopHandle<'static, GuiLayer>,
+    loop_handle: LoopHandle<'static, Self>,
 }
 
 impl CompositorHandler for GuiLayer {
@@ -0,0 +1,560 @@
+use crate::config::IconConfig;
+use crate::desktop::{self, DesktopFile};
+use image::{imageops, RgbaImage};
+use log::debug;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use xdg::BaseDirectories;
+
+const EXTENSIONS: [&str; 2] = ["png", "svg"];
+
+/// Rasterized icons kept on disk, the least recently used ones are removed beyond that.
+const MAX_CACHED_ICONS: usize = 4096;
+
+/// Icon name or path and output scale.
+type IconKey = (String, u32);
+/// A requested icon, `None` if it is missing or broken.
+type LoadedIcon = (IconKey, Option<RgbaImage>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DirType {
+    Fixed,
+    Scalable,
+    Threshold,
+}
+
+/// A subdirectory of an icon theme as described in its `index.theme`.
+#[derive(Debug, Clone)]
+struct ThemeDir {
+    path: String,
+    size: u32,
+    scale: u32,
+    kind: DirType,
+    min_size: u32,
+    max_size: u32,
+    threshold: u32,
+}
+
+impl ThemeDir {
+    fn parse(path: &str, group: &HashMap<String, String>) -> Option<Self> {
+        let get = |key: &str| group.get(key).and_then(|value| value.parse::<u32>().ok());
+        let size = get("Size")?;
+        Some(Self {
+            path: path.to_string(),
+            size,
+            scale: get("Scale").unwrap_or(1),
+            kind: match group.get("Type").map(String::as_str) {
+                Some("Fixed") => DirType::Fixed,
+                Some("Scalable") => DirType::Scalable,
+                _ => DirType::Threshold,
+            },
+            min_size: get("MinSize").unwrap_or(size),
+            max_size: get("MaxSize").unwrap_or(size),
+            threshold: get("Threshold").unwrap_or(2),
+        })
+    }
+
+    const fn matches_size(&self, size: u32, scale: u32) -> bool {
+        if self.scale != scale {
+            return false;
+        }
+        match self.kind {
+            DirType::Fixed => self.size == size,
+            DirType::Scalable => self.min_size <= size && size <= self.max_size,
+            DirType::Threshold => {
+                self.size.saturating_sub(self.threshold) <= size
+                    && size <= self.size + self.threshold
+            }
+        }
+    }
+
+    const fn size_distance(&self, size: u32, scale: u32) -> u32 {
+        let wanted = size * scale;
+        let (min, max) = match self.kind {
+            DirType::Fixed => (self.size, self.size),
+            DirType::Scalable => (self.min_size, self.max_size),
+            DirType::Threshold => (
+                self.size.saturating_sub(self.threshold),
+                self.size + self.threshold,
+            ),
+        };
+        if wanted < min * self.scale {
+            min * self.scale - wanted
+        } else {
+            wanted.saturating_sub(max * self.scale)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Theme {
+    name: String,
+    dirs: Vec<ThemeDir>,
+}
+
+/// Keeps the icons in memory once they are loaded. Loading happens on a
+/// background thread, so drawing a frame never waits for the disk.
+pub struct Icons {
+    config: IconConfig,
+    cache: HashMap<IconKey, Option<RgbaImage>>,
+    /// Requested from the loader, but not received yet
+    pending: HashSet<IconKey>,
+    /// Started with the first request
+    loader: Option<(Sender<IconKey>, Receiver<LoadedIcon>)>,
+}
+
+impl Icons {
+    pub fn new(config: IconConfig) -> Self {
+        Self {
+            config,
+            cache: HashMap::new(),
+            pending: HashSet::new(),
+            loader: None,
+        }
+    }
+
+    /// Size of the rendered icons in pixels, including the output scale.
+    pub const fn size(&self, scale: u32) -> u32 {
+        self.config.size * scale
+    }
+
+    /// Returns the icon for the given name or absolute path, scaled to the configured size.
+    /// Icons that are not loaded yet are requested and show up in a later frame.
+    pub fn get(&mut self, name: &str, scale: u32) -> Option<&RgbaImage> {
+        let key = (name.to_string(), scale);
+        if !self.cache.contains_key(&key) && self.pending.insert(key.clone()) {
+            let (requests, _) = self
+                .loader
+                .get_or_insert_with(|| Loader::spawn(self.config.clone()));
+            if requests.send(key.clone()).is_err() {
+                debug!("Icon loader stopped, can't load {name}");
+            }
+        }
+        self.cache.get(&key).and_then(Option::as_ref)
+    }
+
+    /// Takes over the icons the loader finished since the last frame.
+    pub fn receive(&mut self) {
+        let Some((_, results)) = &self.loader else {
+            return;
+        };
+        for (key, icon) in results.try_iter() {
+            self.pending.remove(&key);
+            self.cache.insert(key, icon);
+        }
+    }
+}
+
+/// Resolves icon names through the freedesktop icon theme specification
+/// and rasterizes them, keeping the results on disk.
+struct Loader {
+    config: IconConfig,
+    base_dirs: Vec<PathBuf>,
+    themes: Option<Vec<Theme>>,
+}
+
+impl Loader {
+    fn new(config: IconConfig) -> Self {
+        let mut base_dirs = Vec::new();
+        if let Some(home) = std::env::var_os("HOME") {
+            base_dirs.push(PathBuf::from(home).join(".icons"));
+        }
+        base_dirs.extend(desktop::data_dirs().iter().map(|dir| dir.join("icons")));
+
+        Self {
+            config,
+            base_dirs,
+            themes: None,
+        }
+    }
+
+    /// Loads the requested icons in order, until the `Icons` that sends them is gone.
+    fn spawn(config: IconConfig) -> (Sender<IconKey>, Receiver<LoadedIcon>) {
+        let (requests, incoming) = mpsc::channel::<IconKey>();
+        let (results, received) = mpsc::channel();
+        thread::spawn(move || {
+            if let Some(dir) = BaseDirectories::with_prefix("kickoff").get_cache_file("icons") {
+                prune_cache(&dir, MAX_CACHED_ICONS);
+            }
+            let mut loader = Self::new(config);
+            for (name, scale) in incoming {
+                let icon = loader
+                    .lookup(&name, scale)
+                    .and_then(|path| loader.load(&path, scale));
+                if results.send(((name, scale), icon)).is_err() {
+                    break;
+                }
+            }
+        });
+        (requests, received)
+    }
+
+    fn lookup(&mut self, name: &str, scale: u32) -> Option<PathBuf> {
+        if name.starts_with('/') {
+            return Path::new(name).exists().then(|| PathBuf::from(name));
+        }
+
+        if self.themes.is_none() {
+            self.themes = Some(self.load_themes());
+        }
+        let size = self.config.size;
+        for theme in self.themes.as_ref().unwrap() {
+            if let Some(path) = self.lookup_in_theme(theme, name, size, scale) {
+                return Some(path);
+            }
+        }
+
+        // Icons outside of any theme
+        let pixmaps = self
+            .base_dirs
+            .iter()
+            .cloned()
+            .chain(std::iter::once(PathBuf::from("/usr/share/pixmaps")));
+        for dir in pixmaps {
+            for extension in EXTENSIONS {
+                let path = dir.join(format!("{name}.{extension}"));
+                if path.is_file() {
+                    return Some(path);
+                }
+            }
+        }
+
+        debug!("Icon {name} not found");
+        None
+    }
+
+    fn lookup_in_theme(&self, theme: &Theme, name: &str, size: u32, scale: u32) -> Option<PathBuf> {
+        for dir in theme
+            .dirs
+            .iter()
+            .filter(|dir| dir.matches_size(size, scale))
+        {
+            if let Some(path) = self.find_in_dir(theme, dir, name) {
+                return Some(path);
+            }
+        }
+
+        let mut closest = None;
+        let mut minimal_distance = u32::MAX;
+        for dir in &theme.dirs {
+            let distance = dir.size_distance(size, scale);
+            if distance < minimal_distance {
+                if let Some(path) = self.find_in_dir(theme, dir, name) {
+                    closest = Some(path);
+                    minimal_distance = distance;
+                }
+            }
+        }
+        closest
+    }
+
+    fn find_in_dir(&self, theme: &Theme, dir: &ThemeDir, name: &str) -> Option<PathBuf> {
+        self.base_dirs
+            .iter()
+            .flat_map(|base| {
+                EXTENSIONS.iter().map(move |extension| {
+                    base.join(&theme.name)
+                        .join(&dir.path)
+                        .join(format!("{name}.{extension}"))
+                })
+            })
+            .find(|path| path.is_file())
+    }
+
+    /// The configured theme followed by everything it inherits from, ending with hicolor.
+    fn load_themes(&self) -> Vec<Theme> {
+        let mut res = Vec::new();
+        let mut queue = vec![self.config.theme.clone()];
+        let mut seen = HashSet::new();
+
+        // hicolor is the implicit parent of every theme and searched last
+        while let Some(name) = queue
+            .pop()
+            .or_else(|| (!seen.contains("hicolor")).then(|| "hicolor".to_string()))
+        {
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            let Some(index) = self
+                .base_dirs
+                .iter()
+                .map(|base| base.join(&name).join("index.theme"))
+                .find_map(|path| fs::read_to_string(path).ok())
+            else {
+                debug!("Icon theme {name} not found");
+                continue;
+            };
+            let index = DesktopFile::parse(&index);
+            let Some(group) = index.group("Icon Theme") else {
+                continue;
+            };
+
+            let dirs = ["Directories", "ScaledDirectories"]
+                .iter()
+                .filter_map(|key| group.get(*key))
+                .flat_map(|dirs| dirs.split(','))
+                .map(str::trim)
+                .filter(|dir| !dir.is_empty())
+                .filter_map(|dir| ThemeDir::parse(dir, index.group(dir)?))
+                .collect();
+
+            // Inherited themes are searched depth first, in the order they are listed
+            let inherits = group.get("Inherits").map_or("", String::as_str);
+            queue.extend(
+                inherits
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|parent| !parent.is_empty())
+                    .map(str::to_string)
+                    .rev(),
+            );
+
+            res.push(Theme { name, dirs });
+        }
+
+        res
+    }
+
+    fn load(&self, path: &Path, scale: u32) -> Option<RgbaImage> {
+        let size = self.config.size * scale;
+        let cache_file = Self::cache_file(path, size);
+
+        if let Some(cache_file) = &cache_file {
+            if let Ok(icon) = image::open(cache_file) {
+                // Mark it as recently used for `prune_cache`
+                if let Err(e) = File::options()
+                    .write(true)
+                    .open(cache_file)
+                    .and_then(|file| file.set_modified(SystemTime::now()))
+                {
+                    debug!("Failed to touch cached icon {}: {e}", cache_file.display());
+                }
+                return Some(icon.to_rgba8());
+            }
+        }
+
+        let icon = if path.extension().is_some_and(|ext| ext == "svg") {
+            Self::render_svg(path, size)
+        } else {
+            image::open(path)
+                .map_err(|e| debug!("Failed to load icon {}: {e}", path.display()))
+                .ok()
+                .map(|icon| {
+                    let icon = icon.to_rgba8();
+                    if icon.width() == size && icon.height() == size {
+                        icon
+                    } else {
+                        imageops::resize(&icon, size, size, imageops::FilterType::Triangle)
+                    }
+                })
+        }?;
+
+        if let Some(cache_file) = cache_file {
+            if let Err(e) = icon.save(&cache_file) {
+                debug!("Failed to cache icon {}: {e}", cache_file.display());
+            }
+        }
+
+        Some(icon)
+    }
+
+    fn render_svg(path: &Path, size: u32) -> Option<RgbaImage> {
+        let data = fs::read(path).ok()?;
+        let tree = resvg::usvg::Tree::from_data(&data, &resvg::usvg::Options::default())
+            .map_err(|e| debug!("Failed to parse icon {}: {e}", path.display()))
+            .ok()?;
+        let mut pixmap = resvg::tiny_skia::Pixmap::new(size, size)?;
+        let tree_size = tree.size();
+        let zoom = size as f32 / tree_size.width().max(tree_size.height());
+        resvg::render(
+            &tree,
+            resvg::tiny_skia::Transform::from_scale(zoom, zoom),
+            &mut pixmap.as_mut(),
+        );
+
+        let pixels = pixmap
+            .pixels()
+            .iter()
+            .flat_map(|pixel| {
+                let color = pixel.demultiply();
+                [color.red(), color.green(), color.blue(), color.alpha()]
+            })
+            .collect();
+        RgbaImage::from_raw(size, size, pixels)
+    }
+
+    /// Location of the rasterized icon, bound to the path and modification time of its source.
+    fn cache_file(path: &Path, size: u32) -> Option<PathBuf> {
+        let modified = path.metadata().ok()?.modified().ok()?;
+        let modified = modified.duration_since(UNIX_EPOCH).ok()?;
+        let mut key = path.as_os_str().as_bytes().to_vec();
+        key.extend(modified.as_secs().to_le_bytes());
+        key.extend(modified.subsec_nanos().to_le_bytes());
+
+        BaseDirectories::with_prefix("kickoff")
+            .place_cache_file(format!("icons/{size}/{:016x}.png", stable_hash(&key)))
+            .ok()
+    }
+}
+
+/// FNV-1a, which unlike `DefaultHasher` stays the same across Rust releases,
+/// so the cached icons outlive a rebuild of kickoff.
+fn stable_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Removes the least recently used icons of the cache directory, one
+/// subdirectory per size, until at most `max` are left.
+fn prune_cache(dir: &Path, max: usize) {
+    let Ok(sizes) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut files = sizes
+        .filter_map(Result::ok)
+        .filter_map(|size| fs::read_dir(size.path()).ok())
+        .flatten()
+        .filter_map(Result::ok)
+        .filter_map(|file| {
+            let modified = file.metadata().ok()?.modified().ok()?;
+            Some((modified, file.path()))
+        })
+        .collect::<Vec<_>>();
+    if files.len() <= max {
+        return;
+    }
+
+    files.sort();
+    for (_, path) in &files[..files.len() - max] {
+        if let Err(e) = fs::remove_file(path) {
+            debug!("Failed to remove cached icon {}: {e}", path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn theme_dir_sizes() {
+        let group = |entries: &[(&str, &str)]| {
+            entries
+                .iter()
+                .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+                .collect::<HashMap<String, String>>()
+        };
+
+        let fixed =
+            ThemeDir::parse("48x48/apps", &group(&[("Size", "48"), ("Type", "Fixed")])).unwrap();
+        assert!(fixed.matches_size(48, 1));
+        assert!(!fixed.matches_size(48, 2));
+        assert!(!fixed.matches_size(32, 1));
+        assert_eq!(fixed.size_distance(32, 1), 16);
+
+        let scalable = ThemeDir::parse(
+            "scalable/apps",
+            &group(&[
+                ("Size", "128"),
+                ("MinSize", "8"),
+                ("MaxSize", "512"),
+                ("Type", "Scalable"),
+            ]),
+        )
+        .unwrap();
+        assert!(scalable.matches_size(32, 1));
+        assert_eq!(scalable.size_distance(1024, 1), 512);
+
+        let threshold = ThemeDir::parse("32x32/apps", &group(&[("Size", "32")])).unwrap();
+        assert!(threshold.matches_size(30, 1));
+        assert!(!threshold.matches_size(29, 1));
+
+        let scaled =
+            ThemeDir::parse("32x32@2/apps", &group(&[("Size", "32"), ("Scale", "2")])).unwrap();
+        assert!(scaled.matches_size(32, 2));
+        assert_eq!(scaled.size_distance(64, 1), 0);
+        assert!(ThemeDir::parse("broken", &group(&[])).is_none());
+    }
+
+    #[test]
+    fn theme_lookup() {
+        let root = TempDir::new("icons");
+        let write = |path: &str, content: &str| {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        };
+        write(
+            "Child/index.theme",
+            "[Icon Theme]\nInherits=Parent\nDirectories=16x16/apps\n\n[16x16/apps]\nSize=16\nType=Fixed\n",
+        );
+        write(
+            "Parent/index.theme",
+            "[Icon Theme]\nDirectories=48x48/apps,scalable/apps\n\n[48x48/apps]\nSize=48\nType=Fixed\n\n[scalable/apps]\nSize=128\nMinSize=8\nMaxSize=512\nType=Scalable\n",
+        );
+        write("Child/16x16/apps/only-small.png", "");
+        write("Parent/48x48/apps/firefox.png", "");
+        write("Parent/scalable/apps/firefox.svg", "");
+
+        let mut icons = Loader {
+            config: IconConfig {
+                enabled: true,
+                theme: "Child".to_string(),
+                size: 32,
+            },
+            base_dirs: vec![root.to_path_buf()],
+            themes: None,
+        };
+        let firefox = icons.lookup("firefox", 1);
+        let small = icons.lookup("only-small", 1);
+        let missing = icons.lookup("missing", 1);
+        let themes: Vec<String> = icons
+            .themes
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|theme| theme.name.clone())
+            .collect();
+
+        assert_eq!(themes, vec!["Child", "Parent"]);
+        assert_eq!(firefox, Some(root.join("Parent/scalable/apps/firefox.svg")));
+        assert_eq!(small, Some(root.join("Child/16x16/apps/only-small.png")));
+        assert_eq!(missing, None);
+    }
+
+    #[test]
+    fn render_svg_icon() {
+        let icon = Loader::render_svg(Path::new("assets/logo.svg"), 48).unwrap();
+        assert_eq!(icon.dimensions(), (48, 48));
+        assert!(icon.pixels().any(|pixel| pixel.0[3] != 0));
+    }
+
+    #[test]
+    fn prune_least_recently_used() {
+        let root = TempDir::new("icon-cache");
+        let now = SystemTime::now();
+        for (size, name, age) in [("32", "old", 30), ("32", "new", 0), ("64", "older", 60)] {
+            let path = root.join(size).join(format!("{name}.png"));
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            let file = File::create(path).unwrap();
+            file.set_modified(now - std::time::Duration::from_secs(age))
+                .unwrap();
+        }
+
+        prune_cache(&root, 2);
+        let left =
+            ["32/old.png", "32/new.png", "64/older.png"].map(|file| root.join(file).exists());
+
+        assert_eq!(left, [true, true, false]);
+        // Reference values of FNV-1a
+        assert_eq!(stable_hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(stable_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+}
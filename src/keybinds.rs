@@ -1,4 +1,5 @@
 use crate::gui::Action;
+use log::warn;
 use serde::de::{self, Visitor};
 use serde::{Deserialize, Deserializer};
 use smithay_client_toolkit::seat::keyboard::{Keysym, Modifiers as ModifiersState};
@@ -9,6 +10,9 @@ use x11_keysymdef::lookup_by_name;
 
 use crate::config::KeybindingsConfig;
 
+/// Custom keybindings exit with 10 and up, limited to 19.
+const MAX_CUSTOM: usize = 10;
+
 pub struct Keybindings {
     inner: HashMap<KeyCombo, Action>,
 }
@@ -21,12 +25,20 @@ impl From<KeybindingsConfig> for Keybindings {
 
         res.add_key_combos(&Action::Complete, &config.complete);
         res.add_key_combos(&Action::Execute, &config.execute);
+        res.add_key_combos(&Action::ExecuteTerminal, &config.execute_terminal);
         res.add_key_combos(&Action::Exit, &config.exit);
         res.add_key_combos(&Action::Delete, &config.delete);
         res.add_key_combos(&Action::DeleteWord, &config.delete_word);
         res.add_key_combos(&Action::NavUp, &config.nav_up);
         res.add_key_combos(&Action::NavDown, &config.nav_down);
         res.add_key_combos(&Action::Paste, &config.paste);
+        res.add_key_combos(&Action::Mark, &config.mark);
+        for (index, key_combos) in config.custom.iter().enumerate().take(MAX_CUSTOM) {
+            res.add_key_combos(&Action::Custom(index as u8), key_combos);
+        }
+        if config.custom.len() > MAX_CUSTOM {
+            warn!("Only the first {MAX_CUSTOM} custom keybindings are used");
+        }
 
         res
     }
@@ -131,3 +143,36 @@ impl Visitor<'_> for KeyComboVisitor {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_keybindings() {
+        let config: KeybindingsConfig =
+            toml::from_str(r#"custom = [["alt+Return"], ["ctrl+d", "ctrl+x"]]"#).unwrap();
+        let keybindings = Keybindings::from(config);
+        let alt = ModifiersState {
+            alt: true,
+            ..ModifiersState::default()
+        };
+        let ctrl = ModifiersState {
+            ctrl: true,
+            ..ModifiersState::default()
+        };
+
+        assert!(matches!(
+            keybindings.get(alt, Keysym::Return),
+            Some(Action::Custom(0))
+        ));
+        assert!(matches!(
+            keybindings.get(ctrl, Keysym::x),
+            Some(Action::Custom(1))
+        ));
+        assert!(matches!(
+            keybindings.get(ModifiersState::default(), Keysym::Return),
+            Some(Action::Execute)
+        ));
+    }
+}
@@ -2,26 +2,39 @@
 #![allow(clippy::cast_possible_truncation)]
 
 use anyhow::Result;
-use app::App;
+use app::{App, Outcome};
 use clap::Parser;
 use config::{Config, History};
+use daemon::{Request, Response};
 use log::{debug, error, warn};
-use std::time::Instant;
-use std::{
-    io::{Read, Write},
-    {fs, io::ErrorKind},
-    {path::PathBuf, process},
-};
-use xdg::BaseDirectories;
+use selection::StdinFormat;
+use smithay_client_toolkit::reexports::calloop::channel;
+use std::io::{ErrorKind, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{path::PathBuf, process};
 
 mod app;
+mod cache;
 mod calculator;
 mod color;
 mod config;
+mod daemon;
+mod desktop;
+mod filter;
 mod font;
 mod gui;
+mod icon;
 mod keybinds;
+mod script;
 mod selection;
+mod terminal;
+#[cfg(test)]
+mod testing;
+
+/// How long to wait for an instance which holds the lock, but has not created its socket yet.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(2);
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(10);
 
 #[derive(Parser, Debug)]
 pub struct Args {
@@ -50,82 +63,271 @@ pub struct Args {
     /// Set custom history name. Default history will only be used if stdin is not set
     #[clap(long)]
     history: Option<PathBuf>,
+
+    /// Ignore the cached application index and rescan all sources
+    #[clap(long)]
+    rebuild_cache: bool,
+
+    /// Keep running in the background with everything loaded, waiting for --show
+    #[clap(
+        long,
+        conflicts_with_all = [
+            "show",
+            "from_stdin",
+            "from_file",
+            "script",
+            "dmenu",
+            "stdout",
+            "multi_select",
+            "print_query",
+            "output",
+        ]
+    )]
+    daemon: bool,
+
+    /// Show the window of a running daemon, starts normally if there is none
+    #[clap(long)]
+    show: bool,
+
+    /// dmenu compatible mode, reads lines from stdin as they are and prints the selection
+    #[clap(long)]
+    dmenu: bool,
+
+    /// Match case insensitive, overwrites config if set
+    #[clap(short = 'i', long)]
+    ignore_case: bool,
+
+    /// Maximum number of results shown at once
+    #[clap(short = 'l', long)]
+    lines: Option<usize>,
+
+    /// Print the index of the selected line instead of the line itself
+    #[clap(long, requires = "dmenu")]
+    index: bool,
+
+    /// Mark several entries and print all of them, implies --stdout
+    #[clap(long)]
+    multi_select: bool,
+
+    /// Separate printed values with NUL instead of newlines
+    #[clap(short = '0', long)]
+    print0: bool,
+
+    /// Read NUL separated records from stdin and files instead of lines
+    #[clap(long)]
+    read0: bool,
+
+    /// Read and print NUL separated records, short for --read0 --print0
+    #[clap(short = 'z', long)]
+    null: bool,
+
+    /// Format of the items read from stdin, files and scripts
+    #[clap(long, value_enum, default_value_t)]
+    format: StdinFormat,
+
+    /// Run a script for the list and again with each selection, until it prints nothing
+    #[clap(long, conflicts_with_all = ["from_stdin", "dmenu", "multi_select"])]
+    script: Option<PathBuf>,
+
+    /// Print the selection as is or as JSON object, with its metadata. Implies --stdout
+    #[clap(long, value_enum, default_value_t)]
+    output: OutputFormat,
+
+    /// Print the matches of a query, best first, without opening a window
+    #[clap(long, value_name = "QUERY", conflicts_with_all = ["daemon", "show", "script"])]
+    filter: Option<String>,
+
+    /// Hide the typed text and print it, without a list or history
+    #[clap(long, conflicts_with_all = [
+        "from_stdin", "from_file", "from_path", "dmenu", "script", "multi_select", "filter", "daemon", "show",
+        "query", "select", "select_index",
+    ])]
+    password: bool,
+
+    /// Start with this query
+    #[clap(long)]
+    query: Option<String>,
+
+    /// Highlight the first result with this name
+    #[clap(long)]
+    select: Option<String>,
+
+    /// Highlight the result at this position, starting at 0
+    #[clap(long, conflicts_with = "select")]
+    select_index: Option<usize>,
+
+    /// Text shown while the query is empty
+    #[clap(long)]
+    placeholder: Option<String>,
+
+    /// Print the typed query on the line before the selection, implies --stdout
+    #[clap(long, conflicts_with_all = ["password", "filter"])]
+    print_query: bool,
+
+    /// Only accept entries of the list, not the typed text
+    #[clap(long, conflicts_with = "password")]
+    only_match: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// The value of the selection
+    #[default]
+    Value,
+    /// The whole item, in the JSON input format
+    Json,
 }
 
-#[cfg(target_os = "linux")]
 #[tokio::main]
 async fn main() -> Result<()> {
     env_logger::init();
-
-    match put_pid() {
-        Ok(()) => {
-            run().await?;
-            del_pid()?;
-            Ok(())
-        }
-        Err(e) => {
-            error!("{e}");
-            Ok(())
+    let mut args = Args::parse();
+    if args.null {
+        args.read0 = true;
+        args.print0 = true;
+    }
+    if args.dmenu {
+        args.from_stdin = true;
+        args.stdout = true;
+        if args.format == StdinFormat::KeyValue {
+            args.format = if args.index {
+                StdinFormat::Index
+            } else {
+                StdinFormat::Plain
+            };
         }
     }
-}
+    if args.multi_select || args.password || args.print_query || args.output == OutputFormat::Json {
+        args.stdout = true;
+    }
 
-#[cfg(not(target_os = "linux"))]
-#[tokio::main]
-async fn main() -> Result<()> {
-    run().await
-}
+    if let Some(query) = args.filter.clone() {
+        return filter::run(args, &query).await;
+    }
 
-#[cfg(target_os = "linux")]
-fn put_pid() -> std::io::Result<()> {
-    let xdg_dirs = BaseDirectories::with_prefix("kickoff");
-    let pid_path = xdg_dirs.place_runtime_file("kickoff.pid").unwrap();
-    match fs::File::open(pid_path.clone()) {
-        Err(_) => {
-            let mut pid_file = fs::File::create(pid_path)?;
-            pid_file.write_all(std::process::id().to_string().as_bytes())?;
-            Ok(())
+    let request = if args.uses_daemon() {
+        Request::Show
+    } else {
+        Request::Status
+    };
+    let start = Instant::now();
+    let server = loop {
+        if args.daemon {
+            if daemon::connect().await.is_some() {
+                error!("Kickoff is already running");
+                return Ok(());
+            }
+        } else {
+            match daemon::ask(request).await? {
+                // A replaced window is already gone once `ask` returns
+                None | Some(Response::Replaced) => {}
+                Some(Response::Shown | Response::Closed) => return not_chosen(&args),
+                Some(Response::Busy) => {
+                    error!("Kickoff is already running");
+                    return not_chosen(&args);
+                }
+                // A daemon without a visible window does not block scripted instances
+                Some(Response::Idle) => return run(args, None).await,
+            }
         }
-        Ok(mut file_handle) => {
-            debug!("Pid file already exists");
-            let mut pid = String::new();
-            file_handle.read_to_string(&mut pid)?;
-            if !pid.is_empty() && fs::metadata(format!("/proc/{pid}")).is_ok() {
-                debug!("Pid from pid file still alive");
-                Err(std::io::Error::new(
-                    ErrorKind::Other,
-                    "Kickoff is already running",
-                ))
-            } else {
-                debug!("Pid from kickoff.pid not alive, overwriting...");
-                let mut pid_file = fs::File::create(pid_path)?;
-                pid_file.write_all(std::process::id().to_string().as_bytes())?;
-                Ok(())
+
+        match daemon::Server::bind() {
+            Ok(server) => break Some(server),
+            // Another instance took the lock first and is about to accept connections
+            Err(e) if e.kind() == ErrorKind::WouldBlock && start.elapsed() < LOCK_TIMEOUT => {
+                tokio::time::sleep(LOCK_POLL_INTERVAL).await;
             }
+            Err(e) => {
+                warn!("Failed to create socket, running without single instance check: {e}");
+                break None;
+            }
+        }
+    };
+    if args.daemon {
+        match server {
+            Some(server) => daemon::run(args, server).await,
+            None => Err(anyhow::anyhow!("The daemon needs a socket to listen on")),
         }
+    } else {
+        run(args, server).await
     }
 }
 
-#[cfg(target_os = "linux")]
-fn del_pid() -> std::io::Result<()> {
-    let xdg_dirs = BaseDirectories::with_prefix("kickoff");
-    let pid_path = xdg_dirs.place_runtime_file("kickoff.pid").unwrap();
-    std::fs::remove_file(pid_path)?;
+/// Ends an invocation that did not get to show its own window. Scripts reading
+/// the selection from stdout must not take that for a successful choice.
+fn not_chosen(args: &Args) -> Result<()> {
+    if args.stdout {
+        process::exit(Outcome::Cancelled.exit_code());
+    }
     Ok(())
 }
 
-async fn run() -> Result<()> {
-    let start = Instant::now();
-    let args = Args::parse();
-    let config = match Config::load(args.config.clone()) {
-        Ok(c) => c,
+impl Args {
+    /// Plain invocations are handled by a running daemon, everything that
+    /// changes the list or its output needs its own instance.
+    fn uses_daemon(&self) -> bool {
+        self.show
+            || (!self.has_custom_list()
+                && !self.from_path
+                && !self.stdout
+                && self.config.is_none()
+                && self.prompt.is_none()
+                && self.history.is_none()
+                && self.query.is_none()
+                && self.select.is_none()
+                && self.select_index.is_none()
+                && self.placeholder.is_none()
+                && !self.only_match
+                && !self.ignore_case
+                && self.lines.is_none())
+    }
+
+    /// Whether typed math is evaluated, which is not wanted for lists of other
+    /// programs and would show secrets typed with `--password`.
+    const fn calculator(&self) -> bool {
+        !self.dmenu && !self.password && self.script.is_none()
+    }
+
+    /// Whether the list comes from stdin, files or a script instead of `$PATH`.
+    fn has_custom_list(&self) -> bool {
+        self.from_stdin || !self.from_file.is_empty() || self.script.is_some()
+    }
+}
+
+fn load_config(args: &Args) -> Config {
+    match Config::load(args.config.clone()) {
+        Ok(mut c) => {
+            c.search.ignore_case |= args.ignore_case;
+            c
+        }
         Err(e) => {
             error!("{e}");
             process::exit(1);
         }
-    };
+    }
+}
 
-    let history = if (!args.from_stdin && args.from_file.is_empty()) || args.history.is_some() {
+async fn load_font(config: &Config) -> std::io::Result<font::Font> {
+    if let Some(font_name) = config.font.clone() {
+        let mut font_names = config.fonts.clone();
+        font_names.insert(0, font_name);
+        font::Font::new(font_names, config.font_size).await
+    } else {
+        font::Font::new(config.fonts.clone(), config.font_size).await
+    }
+}
+
+/// Collects all elements from the sources selected by the arguments, sorted by their history.
+pub async fn load_entries(
+    args: &Args,
+    config: &Config,
+) -> Result<(selection::ElementList, Option<History>)> {
+    // Nothing to choose from, and nothing typed may end up in the history
+    if args.password {
+        return Ok((selection::ElementList::from(Vec::new()), None));
+    }
+
+    let history = if !args.has_custom_list() || args.history.is_some() {
         let path = args.history.clone();
         let decrease_interval = config.history.decrease_interval;
         Some(tokio::task::spawn_blocking(move || {
@@ -135,26 +337,21 @@ async fn run() -> Result<()> {
         None
     };
 
-    let font = if let Some(font_name) = config.font.clone() {
-        let mut font_names = config.fonts.clone();
-        font_names.insert(0, font_name);
-        font::Font::new(font_names, config.font_size)
-    } else {
-        font::Font::new(config.fonts.clone(), config.font_size)
-    };
-
     let mut apps = selection::ElementListBuilder::new();
-    if args.from_path || (!args.from_stdin && args.from_file.is_empty()) {
+    if args.rebuild_cache {
+        apps.rebuild_cache();
+    }
+    if args.from_path || !args.has_custom_list() {
         apps.add_path(config.search.clone());
         apps.add_snap();
         apps.add_flatpak();
-        apps.add_desktop();
+        apps.add_desktop(config.locale.as_deref());
     }
     if !args.from_file.is_empty() {
-        apps.add_files(&args.from_file);
+        apps.add_files(&args.from_file, args.format, args.read0);
     }
-    if args.from_stdin {
-        apps.add_stdin();
+    if let Some(script) = &args.script {
+        apps.add_script(script, args.format);
     }
     let apps = apps.build();
     let mut apps = apps.await?;
@@ -169,9 +366,95 @@ async fn run() -> Result<()> {
     };
     apps.sort_score();
 
+    Ok((apps, history))
+}
+
+async fn run(args: Args, server: Option<daemon::Server>) -> Result<()> {
+    let start = Instant::now();
+    let config = load_config(&args);
+
+    // Tell other instances that a window is already open
+    let session = Arc::new(daemon::Session::visible());
+    let server =
+        server.map(|server| server.spawn_standalone(session.clone(), config.single_instance));
+
+    // Entries from stdin are added to the open window as they arrive
+    let incoming = args.from_stdin.then(|| {
+        let (sender, channel) = channel::channel();
+        let (format, read0) = (args.format, args.read0);
+        tokio::spawn(async move {
+            let result =
+                selection::stream_stdin(format, read0, |elements| sender.send(elements).is_ok());
+            if let Err(e) = result.await {
+                error!("Failed to read stdin: {e}");
+            }
+        });
+        channel
+    });
+
+    let (font, entries) = tokio::join!(load_font(&config), load_entries(&args, &config));
+    let (apps, history) = entries?;
+
     let elapsed = start.elapsed();
     debug!("Time till gui: {elapsed:?}");
-    gui::run(App::new(args, config, apps, font.await?, history));
+    let app = gui::run(
+        App::new(args, config, apps, font?, history),
+        &session.close,
+        incoming,
+    );
+    drop(server);
+
+    if app.args.stdout || matches!(app.outcome, Outcome::CustomKey(_)) {
+        let code = match app.outcome {
+            // dmenu does not tell typed text apart from a selection
+            Outcome::CustomInput if app.args.dmenu || app.args.password => 0,
+            outcome => outcome.exit_code(),
+        };
+        std::io::stdout().flush()?;
+        process::exit(code);
+    }
+    // A read of stdin can't be cancelled, so waiting for it on shutdown would keep
+    // kickoff alive until the input is written to or closed
+    if app.args.from_stdin {
+        process::exit(0);
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn password_skips_list_and_history() {
+        let args = Args::parse_from(["kickoff", "--password", "--history", "/nonexistent"]);
+        let (entries, history) = load_entries(&args, &Config::default()).await.unwrap();
+        assert_eq!(entries.len(), 0);
+        assert!(history.is_none());
+        assert!(!args.calculator());
+        assert!(Args::parse_from(["kickoff"]).calculator());
+
+        let secret = Args::try_parse_from(["kickoff", "--password", "--query", "secret"]);
+        assert!(secret.is_err());
+    }
+
+    #[test]
+    fn daemon_shows_plain_calls() {
+        let uses_daemon = |args: &[&str]| Args::parse_from(args).uses_daemon();
+        assert!(uses_daemon(&["kickoff"]));
+        assert!(uses_daemon(&["kickoff", "--show", "-i"]));
+        assert!(!uses_daemon(&["kickoff", "-i"]));
+        assert!(!uses_daemon(&["kickoff", "-l", "5"]));
+        assert!(!uses_daemon(&["kickoff", "--from-stdin"]));
+    }
+
+    #[test]
+    fn daemon_rejects_own_list_and_output() {
+        assert!(Args::try_parse_from(["kickoff", "--daemon"]).is_ok());
+        for flag in ["--dmenu", "--from-stdin", "--stdout", "--print-query"] {
+            assert!(Args::try_parse_from(["kickoff", "--daemon", flag]).is_err());
+        }
+        assert!(Args::try_parse_from(["kickoff", "--daemon", "--output", "json"]).is_err());
+    }
+}
@@ -0,0 +1,87 @@
+use crate::app::Outcome;
+use crate::selection::{self, Element, StdinFormat};
+use log::{debug, warn};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// `KICKOFF_RETV` of the first run, before anything was selected.
+pub const RETV_INITIAL: i32 = 0;
+
+/// `KICKOFF_RETV` for the way a selection was made, like `ROFI_RETV`:
+/// 1 for an entry, 2 for typed text and 10 to 19 for custom keybindings.
+pub const fn retv(outcome: Outcome) -> i32 {
+    match outcome {
+        Outcome::Selected => 1,
+        outcome => outcome.exit_code(),
+    }
+}
+
+/// Runs the script of `--script` mode and parses its output as the next list.
+///
+/// The value of the selection is passed as the only argument and its `meta`
+/// payload, if any, as `KICKOFF_META`.
+pub fn run(
+    script: &Path,
+    selection: Option<&Element>,
+    retv: i32,
+    format: StdinFormat,
+) -> Result<Vec<Element>, std::io::Error> {
+    let mut command = Command::new(script);
+    command
+        .env("KICKOFF_RETV", retv.to_string())
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit());
+    if let Some(selection) = selection {
+        command.arg(&selection.value);
+        if let Some(meta) = &selection.meta {
+            command.env("KICKOFF_META", meta.to_string());
+        }
+    }
+
+    debug!("Running {} with KICKOFF_RETV={retv}", script.display());
+    let output = command.output()?;
+    if !output.status.success() {
+        warn!("{} exited with {}", script.display(), output.status);
+    }
+
+    let lines = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::to_string)
+        .collect();
+    selection::parse_input(lines, format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn run_script_levels() {
+        let dir = TempDir::new("script");
+        let script = dir.join("menu.sh");
+        fs::write(
+            &script,
+            "#!/bin/sh\ncase \"$1\" in\n  '') printf 'power\\nwifi\\n' ;;\n  power) echo \"reboot $KICKOFF_RETV\" ;;\nesac\n",
+        )
+        .unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let names = |elements: Vec<Element>| {
+            elements
+                .into_iter()
+                .map(|element| element.name)
+                .collect::<Vec<_>>()
+        };
+        let top = run(&script, None, RETV_INITIAL, StdinFormat::Plain).unwrap();
+        assert_eq!(names(top.clone()), vec!["power", "wifi"]);
+
+        let retv = retv(Outcome::Selected);
+        let power = run(&script, Some(&top[0]), retv, StdinFormat::Plain).unwrap();
+        assert_eq!(names(power), vec!["reboot 1"]);
+        let wifi = run(&script, Some(&top[1]), retv, StdinFormat::Plain).unwrap();
+        assert!(wifi.is_empty());
+    }
+}
@@ -1,23 +1,52 @@
+use crate::cache::{self, IndexCache};
 use crate::config::{self, History};
+use crate::desktop::{self, DesktopFile, Environment, FieldCodes};
+use crate::script;
+use clap::ValueEnum;
 use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
-use log::warn;
-use std::fs::File;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::{
     cmp::{Eq, Ord, Ordering, PartialEq, PartialOrd},
-    io::{BufRead, BufReader},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
-use std::{env, os::unix::fs::PermissionsExt, process::Command, fs};
+use std::{env, fs, os::unix::fs::PermissionsExt, process::Command};
 use tokio::{
-    io::{self, AsyncBufReadExt},
-    task::{spawn, spawn_blocking},
+    io::{self, AsyncBufReadExt, AsyncRead, AsyncReadExt},
+    task::spawn_blocking,
 };
 
-#[derive(Eq, PartialEq, Debug, Clone)]
+/// Streamed elements are handed over once this many have arrived, or after `STREAM_INTERVAL`.
+const STREAM_BATCH_SIZE: usize = 1024;
+const STREAM_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Eq, PartialEq, Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Element {
     pub name: String,
     pub value: String,
     pub base_score: usize,
+    /// Arguments to execute directly, bypassing the shell. `value` is still
+    /// kept as an equivalent shell command for history and `--stdout`.
+    pub argv: Option<Vec<String>>,
+    /// Needs to be run inside a terminal emulator
+    pub terminal: bool,
+    /// Directory to change into before executing
+    pub working_dir: Option<PathBuf>,
+    /// Icon name from the icon theme or an absolute path
+    pub icon: Option<String>,
+    /// Secondary fields that are searched with a lower weight than `name`
+    pub generic_name: Option<String>,
+    pub comment: Option<String>,
+    pub keywords: Vec<String>,
+    pub categories: Vec<String>,
+    /// Arbitrary data of JSON input, printed back with `--output json`
+    pub meta: Option<serde_json::Value>,
+    /// Heading of the JSON input that can't be selected, only listed without a query
+    pub header: bool,
 }
 
 impl Ord for Element {
@@ -40,6 +69,12 @@ pub struct ElementList {
     inner: Vec<Element>,
 }
 
+impl From<Vec<Element>> for ElementList {
+    fn from(inner: Vec<Element>) -> Self {
+        Self { inner }
+    }
+}
+
 impl ElementList {
     pub fn merge_history(&mut self, history: &History) {
         for entry in history.as_vec() {
@@ -50,48 +85,197 @@ impl ElementList {
                     name: entry.name.clone(),
                     value: entry.value.clone(),
                     base_score: entry.num_used,
+                    ..Element::default()
                 });
             }
         }
     }
 
     pub fn sort_score(&mut self) {
-        self.inner.sort_by(|a, b| b.base_score.cmp(&a.base_score));
+        self.inner.sort_by_key(|x| std::cmp::Reverse(x.base_score));
+    }
+
+    /// Appends elements that arrived while the window is open, scored by the history
+    /// like in `merge_history`.
+    pub fn extend(&mut self, elements: Vec<Element>, history: Option<&History>) {
+        for mut element in elements {
+            let entry = history.and_then(|history| {
+                history
+                    .as_vec()
+                    .iter()
+                    .find(|entry| entry.name == element.name)
+            });
+            if let Some(entry) = entry {
+                element.base_score = entry.num_used;
+                // Replace the entry `merge_history` added, since it was missing back then
+                if let Some(existing) = self
+                    .inner
+                    .iter_mut()
+                    .find(|x| x.name == element.name && x.value == element.value)
+                {
+                    *existing = element;
+                    continue;
+                }
+            }
+            self.inner.push(element);
+        }
     }
 
-    pub fn search(&self, pattern: &str) -> Vec<&Element> {
-        let matcher = SkimMatcherV2::default();
+    /// The matching elements, best match first.
+    pub fn search(&self, pattern: &str, config: &config::SearchConfig) -> Vec<usize> {
+        let matcher = matcher(config);
         let mut executables = self
             .inner
             .iter()
-            .map(|x| {
-                (
-                    matcher
-                        .fuzzy_match(&x.name, pattern)
-                        .map(|score| score + x.base_score as i64),
-                    x,
-                )
+            .enumerate()
+            .filter(|(_, x)| !x.header || pattern.is_empty())
+            .filter_map(|(index, x)| {
+                Self::score(&matcher, x, pattern, config)
+                    .map(|score| (score + x.base_score as i64, index))
             })
-            .filter(|x| x.0.is_some())
-            .collect::<Vec<(Option<i64>, &Element)>>();
-        executables.sort_by(|a, b| b.0.unwrap_or(0).cmp(&a.0.unwrap_or(0)));
+            .collect::<Vec<(i64, usize)>>();
+        executables.sort_by_key(|x| std::cmp::Reverse(x.0));
         executables.into_iter().map(|x| x.1).collect()
     }
 
+    /// Best weighted match of the pattern against the name and secondary fields of an element.
+    fn score(
+        matcher: &SkimMatcherV2,
+        element: &Element,
+        pattern: &str,
+        config: &config::SearchConfig,
+    ) -> Option<i64> {
+        // A weight of zero disables matching on that field
+        let weighted = |text: &str, weight: f64| {
+            if weight > 0. {
+                matcher
+                    .fuzzy_match(text, pattern)
+                    .map(|score| (score as f64 * weight) as i64)
+            } else {
+                None
+            }
+        };
+        let best = |texts: &[String], weight: f64| {
+            texts.iter().filter_map(|text| weighted(text, weight)).max()
+        };
+
+        [
+            weighted(&element.name, config.name_weight),
+            element
+                .generic_name
+                .as_ref()
+                .and_then(|text| weighted(text, config.generic_name_weight)),
+            best(&element.keywords, config.keywords_weight),
+            element
+                .comment
+                .as_ref()
+                .and_then(|text| weighted(text, config.comment_weight)),
+            best(&element.categories, config.categories_weight),
+        ]
+        .into_iter()
+        .flatten()
+        .max()
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Element> {
+        self.inner.get(index)
+    }
+
     pub fn as_ref_vec(&self) -> Vec<&Element> {
         self.inner.iter().collect()
     }
 }
 
+/// How lines read from stdin are turned into elements.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum StdinFormat {
+    /// `name = value` pairs and `%base_score` directives
+    #[default]
+    KeyValue,
+    /// Every line is an element as is, like in dmenu
+    Plain,
+    /// Like `Plain`, but the value of each element is its line number
+    #[value(skip)]
+    Index,
+    /// A JSON array of items
+    Json,
+    /// One JSON item per line
+    #[value(name = "jsonl")]
+    JsonLines,
+}
+
+/// An item of the JSON input formats, also used to print the selection with `--output json`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JsonItem {
+    pub name: String,
+    /// Defaults to the name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<String>,
+    /// Added to the search score, like `%base_score`
+    pub score: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<serde_json::Value>,
+    /// `false` for headings that group the items below them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selectable: Option<bool>,
+}
+
+impl From<JsonItem> for Element {
+    fn from(item: JsonItem) -> Self {
+        Self {
+            value: item.value.unwrap_or_else(|| item.name.clone()),
+            name: item.name,
+            base_score: item.score,
+            comment: item.description,
+            icon: item.icon,
+            keywords: item.keywords,
+            meta: item.meta,
+            header: item.selectable == Some(false),
+            ..Self::default()
+        }
+    }
+}
+
+impl From<&Element> for JsonItem {
+    fn from(element: &Element) -> Self {
+        Self {
+            name: element.name.clone(),
+            value: Some(element.value.clone()),
+            description: element.comment.clone(),
+            icon: element.icon.clone(),
+            keywords: element.keywords.clone(),
+            score: element.base_score,
+            meta: element.meta.clone(),
+            selectable: element.header.then_some(false),
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct ElementListBuilder {
     path_config: config::SearchConfig,
     from_path: bool,
-    from_stdin: bool,
+    from_script: Option<(PathBuf, StdinFormat)>,
     from_file: Vec<PathBuf>,
+    file_format: StdinFormat,
+    /// Records of `from_file` are separated by NUL instead of newlines
+    read0: bool,
     from_snap: bool,
     from_flatpak: bool,
     from_desktop: bool,
+    desktop_env: Environment,
+    rebuild_cache: bool,
 }
 
 impl ElementListBuilder {
@@ -103,11 +287,14 @@ impl ElementListBuilder {
         self.from_path = true;
         self.path_config = config;
     }
-    pub fn add_files(&mut self, files: &[PathBuf]) {
+    pub fn add_files(&mut self, files: &[PathBuf], format: StdinFormat, read0: bool) {
         self.from_file = files.to_vec();
+        self.file_format = format;
+        self.read0 = read0;
     }
-    pub fn add_stdin(&mut self) {
-        self.from_stdin = true;
+    /// Adds the output of a script, run for the first time.
+    pub fn add_script(&mut self, script: &Path, format: StdinFormat) {
+        self.from_script = Some((script.to_path_buf(), format));
     }
 
     pub fn add_snap(&mut self) {
@@ -118,31 +305,82 @@ impl ElementListBuilder {
         self.from_flatpak = true;
     }
 
-    pub fn add_desktop(&mut self) {
+    /// Adds desktop entries, localized for the given locale or the one from the environment.
+    pub fn add_desktop(&mut self, locale: Option<&str>) {
         self.from_desktop = true;
+        self.desktop_env = Environment::from_env(locale);
+    }
+
+    /// Ignore the index cache and rescan every source.
+    pub fn rebuild_cache(&mut self) {
+        self.rebuild_cache = true;
     }
 
     pub async fn build(&self) -> Result<ElementList, std::io::Error> {
+        let cache = if self.from_path || self.from_snap || self.from_flatpak || self.from_desktop {
+            let rebuild = self.rebuild_cache;
+            Arc::new(spawn_blocking(move || IndexCache::load(rebuild)).await?)
+        } else {
+            Arc::new(IndexCache::default())
+        };
+
         let mut fut = Vec::new();
-        if self.from_stdin {
-            fut.push(spawn(Self::build_stdin()));
+        if let Some((script, format)) = self.from_script.clone() {
+            fut.push(spawn_blocking(move || {
+                script::run(&script, None, script::RETV_INITIAL, format)
+            }));
         }
         if !self.from_file.is_empty() {
             let files = self.from_file.clone();
-            fut.push(spawn_blocking(move || Self::build_files(&files)));
+            let (format, read0) = (self.file_format, self.read0);
+            fut.push(spawn_blocking(move || {
+                Self::build_files(&files, format, read0)
+            }));
         }
         if self.from_path {
             let show_hidden = self.path_config.show_hidden_files;
-            fut.push(spawn_blocking(move || Self::build_path(show_hidden)));
+            let cache = cache.clone();
+            fut.push(spawn_blocking(move || {
+                Self::build_path(show_hidden, &cache)
+            }));
         }
         if self.from_snap {
-            fut.push(spawn_blocking(Self::build_snap));
+            let cache = cache.clone();
+            fut.push(spawn_blocking(move || {
+                cache.get_or_build(
+                    "snap",
+                    cache::fingerprint([Path::new("/snap")], ""),
+                    Self::build_snap,
+                )
+            }));
         }
         if self.from_flatpak {
-            fut.push(spawn_blocking(Self::build_flatpak));
+            let cache = cache.clone();
+            let from_desktop = self.from_desktop;
+            fut.push(spawn_blocking(move || {
+                let installations = Self::flatpak_installations();
+                let elements = cache.get_or_build(
+                    "flatpak",
+                    cache::fingerprint(installations.iter().map(PathBuf::as_path), ""),
+                    Self::build_flatpak,
+                )?;
+                if !from_desktop {
+                    return Ok(elements);
+                }
+                // Apps that export a desktop file are listed with the desktop entries already
+                let exported = desktop::find_desktop_files(&desktop::data_dirs())
+                    .into_iter()
+                    .map(|(id, _)| id)
+                    .collect();
+                Ok(Self::without_exported(elements, &exported))
+            }));
         }
         if self.from_desktop {
-            fut.push(spawn_blocking(Self::build_desktop));
+            let desktop_env = self.desktop_env.clone();
+            let cache = cache.clone();
+            fut.push(spawn_blocking(move || {
+                Self::build_desktop_cached(&desktop_env, &cache)
+            }));
         }
 
         let finished = futures::future::join_all(fut).await;
@@ -153,76 +391,47 @@ impl ElementListBuilder {
             res.append(&mut elements);
         }
 
+        if let Err(e) = spawn_blocking(move || cache.save()).await? {
+            warn!("Failed to save index cache: {e}");
+        }
+
         Ok(ElementList { inner: res })
     }
 
-    fn build_files(files: &[PathBuf]) -> Result<Vec<Element>, std::io::Error> {
+    fn build_files(
+        files: &[PathBuf],
+        format: StdinFormat,
+        read0: bool,
+    ) -> Result<Vec<Element>, std::io::Error> {
+        let delimiter = delimiter(read0);
         let mut res = Vec::new();
         for file in files {
-            let mut reader = BufReader::new(File::open(file)?);
-            let mut buf = String::new();
-            let mut base_score = 0;
-
-            while reader.read_line(&mut buf)? > 0 {
-                let kv_pair = match parse_line(&buf) {
-                    None => continue,
-                    Some(res) => res,
-                };
-                match kv_pair {
-                    ("%base_score", Some(value)) => {
-                        if let Ok(value) = value.parse::<usize>() {
-                            base_score = value;
-                        }
-                    }
-                    (key, Some(value)) => res.push(Element {
-                        name: key.to_string(),
-                        value: value.to_string(),
-                        base_score,
-                    }),
-                    ("", None) => {} // Empty Line
-                    (key, None) => res.push(Element {
-                        name: key.to_string(),
-                        value: key.to_string(),
-                        base_score,
-                    }),
-                }
-
-                buf.clear();
+            let content = fs::read(file)?;
+            if format == StdinFormat::Json {
+                res.extend(parse_json(&String::from_utf8_lossy(&content))?);
+                continue;
+            }
+            let mut parser = LineParser::new(format);
+            for record in content.split(|byte| *byte == delimiter) {
+                res.extend(parser.parse(&to_record(record.to_vec(), delimiter))?);
             }
         }
 
         Ok(res)
     }
 
-    fn build_path(show_hidden: bool) -> Result<Vec<Element>, std::io::Error> {
+    fn build_path(show_hidden: bool, cache: &IndexCache) -> Result<Vec<Element>, std::io::Error> {
         let var = env::var("PATH").unwrap();
 
         let mut res: Vec<Element> = Vec::new();
 
-        let paths_iter = env::split_paths(&var);
-        let dirs_iter = paths_iter.filter_map(|path| std::fs::read_dir(path).ok());
-
-        for dir in dirs_iter {
-            dir.filter_map(Result::ok).for_each(|file| {
-                if !show_hidden
-                    && file
-                        .file_name()
-                        .to_str()
-                        .is_some_and(|name| name.starts_with('.'))
-                {
-                    return;
-                }
-                if let Ok(metadata) = file.metadata() {
-                    if !metadata.is_dir() && metadata.permissions().mode() & 0o111 != 0 {
-                        let name = file.file_name().to_str().unwrap().to_string();
-                        res.push(Element {
-                            value: name.clone(),
-                            name,
-                            base_score: 0,
-                        });
-                    }
-                }
-            });
+        for path in env::split_paths(&var) {
+            let fingerprint = cache::fingerprint([path.as_path()], &show_hidden);
+            let mut elements =
+                cache.get_or_build(&format!("path:{}", path.display()), fingerprint, || {
+                    Ok(Self::build_path_dir(&path, show_hidden))
+                })?;
+            res.append(&mut elements);
         }
 
         res.sort();
@@ -231,39 +440,34 @@ impl ElementListBuilder {
         Ok(res)
     }
 
-    async fn build_stdin() -> Result<Vec<Element>, std::io::Error> {
-        let stdin = io::stdin();
-        let reader = io::BufReader::new(stdin);
-        let mut lines = reader.lines();
+    fn build_path_dir(path: &Path, show_hidden: bool) -> Vec<Element> {
         let mut res = Vec::new();
-        let mut base_score = 0;
+        let Ok(dir) = fs::read_dir(path) else {
+            return res;
+        };
 
-        while let Some(line) = lines.next_line().await? {
-            let kv_pair = match parse_line(&line) {
-                None => continue,
-                Some(res) => res,
-            };
-            match kv_pair {
-                ("%base_score", Some(value)) => {
-                    if let Ok(value) = value.parse::<usize>() {
-                        base_score = value;
-                    }
+        dir.filter_map(Result::ok).for_each(|file| {
+            if !show_hidden
+                && file
+                    .file_name()
+                    .to_str()
+                    .is_some_and(|name| name.starts_with('.'))
+            {
+                return;
+            }
+            if let Ok(metadata) = file.metadata() {
+                if !metadata.is_dir() && metadata.permissions().mode() & 0o111 != 0 {
+                    let name = file.file_name().to_str().unwrap().to_string();
+                    res.push(Element {
+                        value: name.clone(),
+                        name,
+                        ..Element::default()
+                    });
                 }
-                (key, Some(value)) => res.push(Element {
-                    name: key.to_string(),
-                    value: value.to_string(),
-                    base_score,
-                }),
-                ("", None) => {} // Empty Line
-                (key, None) => res.push(Element {
-                    name: key.to_string(),
-                    value: key.to_string(),
-                    base_score,
-                }),
             }
-        }
+        });
 
-        Ok(res)
+        res
     }
 
     fn build_snap() -> Result<Vec<Element>, std::io::Error> {
@@ -286,10 +490,15 @@ impl ElementListBuilder {
                 if name.starts_with("core") || name == "snapd" {
                     continue;
                 }
+                let icon = ["png", "svg"]
+                    .iter()
+                    .map(|ext| format!("/snap/{name}/current/meta/gui/icon.{ext}"))
+                    .find(|path| Path::new(path).exists());
                 res.push(Element {
                     name: name.to_string(),
                     value: name.to_string(),
-                    base_score: 0,
+                    icon,
+                    ..Element::default()
                 });
             }
         }
@@ -297,9 +506,18 @@ impl ElementListBuilder {
         Ok(res)
     }
 
+    /// Directories that change whenever a Flatpak application is (un)installed.
+    pub fn flatpak_installations() -> Vec<PathBuf> {
+        let mut res = vec![PathBuf::from("/var/lib/flatpak/app")];
+        if let Some(home) = env::var_os("HOME") {
+            res.push(PathBuf::from(home).join(".local/share/flatpak/app"));
+        }
+        res
+    }
+
     fn build_flatpak() -> Result<Vec<Element>, std::io::Error> {
         let output = match Command::new("flatpak")
-            .args(&["list", "--app", "--columns=application,name"])
+            .args(["list", "--app", "--columns=application,name"])
             .output()
         {
             Ok(output) => output,
@@ -318,19 +536,21 @@ impl ElementListBuilder {
             if parts.len() >= 2 {
                 let app_id = parts[0].trim();
                 let display_name = parts[1].trim();
-                
+
                 if !app_id.is_empty() {
                     let name = if display_name.is_empty() {
                         // Use the app ID without the domain part as display name
-                        app_id.split('.').last().unwrap_or(app_id).to_string()
+                        app_id.split('.').next_back().unwrap_or(app_id).to_string()
                     } else {
                         display_name.to_string()
                     };
-                    
+
                     res.push(Element {
                         name,
-                        value: format!("flatpak run {}", app_id),
-                        base_score: 0,
+                        value: format!("flatpak run {app_id}"),
+                        // Flatpak exports icons named after the application ID
+                        icon: Some(app_id.to_string()),
+                        ..Element::default()
                     });
                 }
             }
@@ -339,107 +559,429 @@ impl ElementListBuilder {
         Ok(res)
     }
 
-    fn build_desktop() -> Result<Vec<Element>, std::io::Error> {
+    /// Desktop entries are cached as a whole, since IDs shadow each other
+    /// across directories. Besides the files themselves, the result depends on
+    /// the locale, the current desktop and `$PATH` for `TryExec`.
+    /// Drops the Flatpak apps whose `<app_id>.desktop` is among the given desktop file IDs.
+    fn without_exported(elements: Vec<Element>, exported: &HashSet<String>) -> Vec<Element> {
+        elements
+            .into_iter()
+            .filter(|element| {
+                !element
+                    .value
+                    .strip_prefix("flatpak run ")
+                    .is_some_and(|app_id| exported.contains(&format!("{app_id}.desktop")))
+            })
+            .collect()
+    }
+
+    fn build_desktop_cached(
+        desktop_env: &Environment,
+        cache: &IndexCache,
+    ) -> Result<Vec<Element>, std::io::Error> {
+        let data_dirs = desktop::data_dirs();
+        let files = desktop::find_desktop_files(&data_dirs);
+        let path_var = env::var_os("PATH").unwrap_or_default();
+        let path_dirs: Vec<PathBuf> = env::split_paths(&path_var).collect();
+        let app_dirs: Vec<PathBuf> = data_dirs
+            .iter()
+            .map(|dir| dir.join("applications"))
+            .collect();
+
+        let fingerprint = cache::fingerprint(
+            files
+                .iter()
+                .map(|(_, path)| path.as_path())
+                .chain(app_dirs.iter().map(PathBuf::as_path))
+                .chain(path_dirs.iter().map(PathBuf::as_path)),
+            desktop_env,
+        );
+        cache.get_or_build("desktop", fingerprint, || {
+            Self::build_desktop(desktop_env, files)
+        })
+    }
+
+    fn build_desktop(
+        desktop_env: &Environment,
+        files: Vec<(String, PathBuf)>,
+    ) -> Result<Vec<Element>, std::io::Error> {
         let mut res = Vec::new();
-        
-        // Standard desktop file locations
-        let desktop_dirs = [
-            "/usr/share/applications",
-            "/usr/local/share/applications",
-            &format!("{}/.local/share/applications", env::var("HOME").unwrap_or_default()),
-        ];
-
-        for dir_path in &desktop_dirs {
-            if let Ok(entries) = fs::read_dir(dir_path) {
-                for entry in entries.flatten() {
-                    if let Some(file_name) = entry.file_name().to_str() {
-                        if file_name.ends_with(".desktop") {
-                            if let Ok(content) = fs::read_to_string(entry.path()) {
-                                if let Some(element) = Self::parse_desktop_file(&content) {
-                                    res.push(element);
-                                }
-                            }
-                        }
-                    }
-                }
+
+        // A file that shadows another one with the same ID also masks it
+        // when it is hidden, so this has to happen before parsing.
+        for (_, path) in files {
+            if let Ok(content) = fs::read_to_string(&path) {
+                res.append(&mut Self::parse_desktop_file(&content, &path, desktop_env));
             }
         }
 
-        // Remove duplicates by name, keeping the first occurrence
-        res.sort_by(|a, b| a.name.cmp(&b.name));
-        res.dedup_by(|a, b| a.name == b.name);
+        res.sort();
 
         Ok(res)
     }
 
-    fn parse_desktop_file(content: &str) -> Option<Element> {
-        let mut name = None;
-        let mut exec = None;
-        let mut hidden = false;
-        let mut no_display = false;
-        let mut app_type = None;
-        let mut in_desktop_entry = false;
+    fn parse_desktop_file(
+        content: &str,
+        location: &Path,
+        desktop_env: &Environment,
+    ) -> Vec<Element> {
+        let locale = desktop_env.locale.as_ref();
+        let file = DesktopFile::parse(content);
+        let Some(entry) = file.group("Desktop Entry") else {
+            return Vec::new();
+        };
+        let is_true = |key: &str| {
+            entry
+                .get(key)
+                .is_some_and(|v| v.eq_ignore_ascii_case("true"))
+        };
+
+        if is_true("Hidden") {
+            return Vec::new();
+        }
+
+        // Allow settings applications even if NoDisplay=true (like Cosmic settings panels)
+        let is_settings = entry.get("Type").is_some_and(|t| t == "Settings");
+        let is_cosmic_settings = entry
+            .get("Exec")
+            .is_some_and(|e| e.contains("cosmic-settings"));
+
+        if is_true("NoDisplay") && !is_settings && !is_cosmic_settings {
+            return Vec::new();
+        }
+
+        if !desktop_env.shows(
+            entry.get("OnlyShowIn").map(String::as_str),
+            entry.get("NotShowIn").map(String::as_str),
+        ) {
+            return Vec::new();
+        }
+
+        // Leftover entries of uninstalled applications
+        if let Some(try_exec) = entry.get("TryExec") {
+            if !desktop::executable_exists(&desktop::unescape_value(try_exec)) {
+                return Vec::new();
+            }
+        }
+
+        let Some(name) =
+            desktop::localized(entry, "Name", locale).map(|name| desktop::unescape_value(name))
+        else {
+            return Vec::new();
+        };
+        let icon = entry.get("Icon").map(|icon| desktop::unescape_value(icon));
+        // Shared by the entry and its actions
+        let details = Element {
+            generic_name: desktop::localized(entry, "GenericName", locale)
+                .map(|generic_name| desktop::unescape_value(generic_name)),
+            comment: desktop::localized(entry, "Comment", locale)
+                .map(|comment| desktop::unescape_value(comment)),
+            keywords: desktop::localized(entry, "Keywords", locale)
+                .map(|keywords| desktop::parse_list(keywords))
+                .unwrap_or_default(),
+            categories: entry
+                .get("Categories")
+                .map(|categories| desktop::parse_list(categories))
+                .unwrap_or_default(),
+            terminal: is_true("Terminal"),
+            working_dir: entry
+                .get("Path")
+                .map(|path| desktop::unescape_value(path))
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from),
+            ..Element::default()
+        };
+
+        let mut res = Vec::new();
+        if let Some(element) = Self::desktop_element(
+            name.clone(),
+            entry,
+            &name,
+            icon.as_deref(),
+            location,
+            &details,
+        ) {
+            res.push(element);
+        }
 
-        for line in content.lines() {
-            let line = line.trim();
-            
-            if line == "[Desktop Entry]" {
-                in_desktop_entry = true;
+        for action in entry
+            .get("Actions")
+            .map(|actions| desktop::parse_list(actions))
+            .unwrap_or_default()
+        {
+            let Some(group) = file.group(&format!("Desktop Action {action}")) else {
+                debug!(
+                    "Missing group for action {action} in {}",
+                    location.display()
+                );
                 continue;
-            } else if line.starts_with('[') && line.ends_with(']') {
-                in_desktop_entry = false;
+            };
+            let Some(action_name) =
+                desktop::localized(group, "Name", locale).map(|name| desktop::unescape_value(name))
+            else {
                 continue;
+            };
+            let action_icon = group
+                .get("Icon")
+                .map(|icon| desktop::unescape_value(icon))
+                .or_else(|| icon.clone());
+            if let Some(element) = Self::desktop_element(
+                format!("{name}: {action_name}"),
+                group,
+                &action_name,
+                action_icon.as_deref(),
+                location,
+                &details,
+            ) {
+                res.push(element);
             }
+        }
 
-            if !in_desktop_entry {
-                continue;
+        res
+    }
+
+    /// Builds an element from the Exec key of a `[Desktop Entry]` or `[Desktop Action]` group.
+    fn desktop_element(
+        display_name: String,
+        group: &HashMap<String, String>,
+        name: &str,
+        icon: Option<&str>,
+        location: &Path,
+        details: &Element,
+    ) -> Option<Element> {
+        let exec = group.get("Exec")?;
+        let codes = FieldCodes {
+            name,
+            icon,
+            location: Some(location),
+        };
+        match desktop::parse_exec(exec, &codes) {
+            Ok(argv) => Some(Element {
+                name: display_name,
+                value: desktop::shell_join(&argv),
+                argv: Some(argv),
+                icon: icon.map(str::to_string),
+                ..details.clone()
+            }),
+            Err(e) => {
+                debug!("Skipping {display_name} in {}: {e}", location.display());
+                None
             }
+        }
+    }
+}
+
+/// Turns the lines of stdin or a script into elements.
+pub fn parse_input(
+    lines: Vec<String>,
+    format: StdinFormat,
+) -> Result<Vec<Element>, std::io::Error> {
+    if format == StdinFormat::Json {
+        return parse_json(&lines.join("\n"));
+    }
+    let mut parser = LineParser::new(format);
+    lines
+        .iter()
+        .filter_map(|line| parser.parse(line).transpose())
+        .collect()
+}
+
+/// Reads stdin and hands the elements over in batches as they arrive, so the
+/// window does not have to wait for the end of the input. Stops early once
+/// `send` returns false. With `read0`, records are separated by NUL instead of newlines.
+pub async fn stream_stdin<F>(
+    format: StdinFormat,
+    read0: bool,
+    send: F,
+) -> Result<(), std::io::Error>
+where
+    F: FnMut(Vec<Element>) -> bool,
+{
+    stream_records(io::stdin(), format, read0, send).await
+}
+
+async fn stream_records<R, F>(
+    reader: R,
+    format: StdinFormat,
+    read0: bool,
+    mut send: F,
+) -> Result<(), std::io::Error>
+where
+    R: AsyncRead + Unpin,
+    F: FnMut(Vec<Element>) -> bool,
+{
+    let mut reader = io::BufReader::new(reader);
+
+    // A JSON array is only complete at the end of the input
+    if format == StdinFormat::Json {
+        let mut input = String::new();
+        reader.read_to_string(&mut input).await?;
+        send(parse_json(&input)?);
+        return Ok(());
+    }
 
-            if let Some(equals_pos) = line.find('=') {
-                let key = &line[..equals_pos];
-                let value = &line[equals_pos + 1..];
-
-                match key {
-                    "Name" => name = Some(value.to_string()),
-                    "Exec" => exec = Some(value.to_string()),
-                    "Hidden" => hidden = value.eq_ignore_ascii_case("true"),
-                    "NoDisplay" => no_display = value.eq_ignore_ascii_case("true"),
-                    "Type" => app_type = Some(value.to_string()),
-                    _ => {}
+    let delimiter = delimiter(read0);
+    let mut records = reader.split(delimiter);
+
+    let mut parser = LineParser::new(format);
+    let mut batch = Vec::new();
+    let mut last_sent = Instant::now();
+    loop {
+        let record = if batch.is_empty() {
+            records.next_segment().await?
+        } else {
+            // Don't hold back what has arrived so far if the input stalls
+            let remaining = STREAM_INTERVAL.saturating_sub(last_sent.elapsed());
+            match tokio::time::timeout(remaining, records.next_segment()).await {
+                Ok(record) => record?,
+                Err(_) => {
+                    if !send(std::mem::take(&mut batch)) {
+                        return Ok(());
+                    }
+                    last_sent = Instant::now();
+                    continue;
                 }
             }
+        };
+        let Some(record) = record else {
+            break;
+        };
+
+        batch.extend(parser.parse(&to_record(record, delimiter))?);
+        if batch.len() >= STREAM_BATCH_SIZE || last_sent.elapsed() >= STREAM_INTERVAL {
+            if !send(std::mem::take(&mut batch)) {
+                return Ok(());
+            }
+            last_sent = Instant::now();
         }
+    }
+
+    if !batch.is_empty() {
+        send(batch);
+    }
+    Ok(())
+}
+
+fn matcher(config: &config::SearchConfig) -> SkimMatcherV2 {
+    if config.ignore_case {
+        SkimMatcherV2::default().ignore_case()
+    } else {
+        SkimMatcherV2::default()
+    }
+}
+
+/// Positions of the characters of a name that match the query. Only computed for
+/// the visible results, since it is a lot slower than scoring.
+pub fn match_positions(name: &str, pattern: &str, config: &config::SearchConfig) -> Vec<usize> {
+    if config.name_weight <= 0. {
+        return Vec::new();
+    }
+    matcher(config)
+        .fuzzy_indices(name, pattern)
+        .map(|(_, positions)| positions)
+        .unwrap_or_default()
+}
+
+/// Byte that separates records, NUL with `--read0`.
+const fn delimiter(read0: bool) -> u8 {
+    if read0 {
+        b'\0'
+    } else {
+        b'\n'
+    }
+}
+
+/// Decodes a record without its delimiter, dropping the carriage return of a CRLF line.
+fn to_record(bytes: Vec<u8>, delimiter: u8) -> String {
+    let mut record = String::from_utf8(bytes)
+        .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned());
+    if delimiter == b'\n' && record.ends_with('\r') {
+        record.pop();
+    }
+    record
+}
+
+/// Parses the line based formats one line at a time, keeping `%base_score`
+/// and the line number in between.
+struct LineParser {
+    format: StdinFormat,
+    base_score: usize,
+    index: usize,
+}
 
-        if hidden {
-            return None;
+impl LineParser {
+    const fn new(format: StdinFormat) -> Self {
+        Self {
+            format,
+            base_score: 0,
+            index: 0,
         }
-        
-        // Allow settings applications even if NoDisplay=true (like Cosmic settings panels)
-        let is_settings = app_type.as_ref().map_or(false, |t| t == "Settings");
-        let is_cosmic_settings = exec.as_ref().map_or(false, |e| e.contains("cosmic-settings"));
-        
-        if no_display && !is_settings && !is_cosmic_settings {
-            return None;
-        }
-
-        if let (Some(name), Some(mut exec)) = (name, exec) {
-            // Clean up exec command - remove field codes like %f, %F, %u, %U
-            exec = exec.replace("%f", "").replace("%F", "")
-                      .replace("%u", "").replace("%U", "")
-                      .replace("%i", "").replace("%c", "")
-                      .replace("%k", "").trim().to_string();
-
-            Some(Element {
-                name,
-                value: exec,
-                base_score: 0,
-            })
-        } else {
-            None
+    }
+
+    fn parse(&mut self, line: &str) -> Result<Option<Element>, std::io::Error> {
+        let index = self.index;
+        self.index += 1;
+
+        match self.format {
+            StdinFormat::Plain | StdinFormat::Index => {
+                if line.is_empty() {
+                    return Ok(None);
+                }
+                Ok(Some(Element {
+                    value: if self.format == StdinFormat::Index {
+                        index.to_string()
+                    } else {
+                        line.to_string()
+                    },
+                    name: line.to_string(),
+                    ..Element::default()
+                }))
+            }
+            // Whole JSON arrays are handled by `parse_json`
+            StdinFormat::JsonLines | StdinFormat::Json => {
+                if line.trim().is_empty() {
+                    return Ok(None);
+                }
+                parse_json_item(line).map(Some)
+            }
+            StdinFormat::KeyValue => Ok(self.parse_key_value(line)),
+        }
+    }
+
+    fn parse_key_value(&mut self, line: &str) -> Option<Element> {
+        match parse_line(line)? {
+            ("%base_score", Some(value)) => {
+                if let Ok(value) = value.parse::<usize>() {
+                    self.base_score = value;
+                }
+                None
+            }
+            (key, Some(value)) => Some(Element {
+                name: key.to_string(),
+                value: value.to_string(),
+                base_score: self.base_score,
+                ..Element::default()
+            }),
+            ("", None) => None, // Empty Line
+            (key, None) => Some(Element {
+                name: key.to_string(),
+                value: key.to_string(),
+                base_score: self.base_score,
+                ..Element::default()
+            }),
         }
     }
 }
 
+fn parse_json(input: &str) -> Result<Vec<Element>, std::io::Error> {
+    let items: Vec<JsonItem> = serde_json::from_str(input)?;
+    Ok(items.into_iter().map(Element::from).collect())
+}
+
+fn parse_json_item(line: &str) -> Result<Element, std::io::Error> {
+    Ok(serde_json::from_str::<JsonItem>(line)?.into())
+}
+
 #[allow(clippy::type_complexity)]
 fn parse_line(input: &str) -> Option<(&str, Option<&str>)> {
     let input = input.trim();
@@ -456,6 +998,8 @@ fn parse_line(input: &str) -> Option<(&str, Option<&str>)> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::TempDir;
+    use tokio::io::AsyncWriteExt;
 
     #[test]
     fn parse_line_test() {
@@ -477,4 +1021,327 @@ mod tests {
             ))
         );
     }
+
+    #[test]
+    fn parse_lines_formats() {
+        let lines = vec!["foo = bar".to_string(), String::new(), " a=b ".to_string()];
+        let names = |format| {
+            parse_input(lines.clone(), format)
+                .unwrap()
+                .into_iter()
+                .map(|element| (element.name, element.value))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            names(StdinFormat::KeyValue),
+            vec![
+                ("foo".to_string(), "bar".to_string()),
+                ("a".to_string(), "b".to_string())
+            ]
+        );
+        assert_eq!(
+            names(StdinFormat::Plain),
+            vec![
+                ("foo = bar".to_string(), "foo = bar".to_string()),
+                (" a=b ".to_string(), " a=b ".to_string())
+            ]
+        );
+        assert_eq!(
+            names(StdinFormat::Index),
+            vec![
+                ("foo = bar".to_string(), "0".to_string()),
+                (" a=b ".to_string(), "2".to_string())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn stream_records_batches() {
+        let (mut writer, reader) = io::duplex(64);
+        let feed = async move {
+            writer.write_all(b"a\nb\n").await.unwrap();
+            // Stall longer than a batch may be held back
+            tokio::time::sleep(STREAM_INTERVAL * 4).await;
+            writer.write_all(b"c\n").await.unwrap();
+        };
+        let mut batches = Vec::new();
+        let stream = stream_records(reader, StdinFormat::Plain, false, |batch| {
+            batches.push(
+                batch
+                    .into_iter()
+                    .map(|element| element.name)
+                    .collect::<Vec<_>>(),
+            );
+            true
+        });
+        let ((), result) = tokio::join!(feed, stream);
+        result.unwrap();
+
+        assert_eq!(batches, vec![vec!["a", "b"], vec!["c"]]);
+    }
+
+    #[test]
+    fn extend_merges_history() {
+        let element = |name: &str| Element {
+            name: name.to_string(),
+            value: name.to_string(),
+            ..Element::default()
+        };
+        let dir = TempDir::new("history");
+        let mut history = History::load(Some(dir.join("history.csv")), 0).unwrap();
+        history.inc(&element("b"));
+        history.inc(&element("b"));
+
+        // Entries of the history are added before the elements streamed in
+        let mut list = ElementList::from(vec![element("a")]);
+        list.merge_history(&history);
+        list.extend(vec![element("b"), element("c")], Some(&history));
+
+        let entries = list
+            .as_ref_vec()
+            .into_iter()
+            .map(|element| (element.name.as_str(), element.base_score))
+            .collect::<Vec<_>>();
+        assert_eq!(entries, vec![("a", 0), ("b", 2), ("c", 0)]);
+    }
+
+    #[test]
+    fn build_files_read0() {
+        let dir = TempDir::new("read0");
+        let file = dir.join("list");
+        fs::write(&file, "two\nlines = echo\0%base_score = 5\0plain\0").unwrap();
+
+        let files = vec![file];
+        let elements =
+            ElementListBuilder::build_files(&files, StdinFormat::KeyValue, true).unwrap();
+        let lines = ElementListBuilder::build_files(&files, StdinFormat::KeyValue, false).unwrap();
+        let plain = ElementListBuilder::build_files(&files, StdinFormat::Plain, true).unwrap();
+
+        // --format applies to files as well
+        assert_eq!(plain[0].name, "two\nlines = echo");
+        assert_eq!(plain[1].name, "%base_score = 5");
+
+        let names = elements
+            .into_iter()
+            .map(|element| (element.name, element.value, element.base_score))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                ("two\nlines".to_string(), "echo".to_string(), 0),
+                ("plain".to_string(), "plain".to_string(), 5)
+            ]
+        );
+        // Without --read0 the NUL bytes are part of the lines
+        let lines = lines
+            .into_iter()
+            .map(|element| (element.name, element.value))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                ("two".to_string(), "two".to_string()),
+                (
+                    "lines".to_string(),
+                    "echo\0%base_score = 5\0plain\0".to_string()
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_json_items() {
+        let elements = parse_json(
+            r#"[{"name": "a=b", "meta": {"id": 1}}, {"name": "c", "value": "d", "score": 2}]"#,
+        )
+        .unwrap();
+        assert_eq!(elements[0].name, "a=b");
+        assert_eq!(elements[0].value, "a=b");
+        assert_eq!(elements[1].value, "d");
+        assert_eq!(elements[1].base_score, 2);
+
+        let item = JsonItem::from(&elements[0]);
+        assert_eq!(
+            serde_json::to_string(&item).unwrap(),
+            r#"{"name":"a=b","value":"a=b","score":0,"meta":{"id":1}}"#
+        );
+
+        let element =
+            parse_json_item(r#"{"name": "x", "description": "y", "keywords": ["z"]}"#).unwrap();
+        assert_eq!(element.comment.as_deref(), Some("y"));
+        assert_eq!(element.keywords, vec!["z"]);
+        assert!(parse_json_item("name = value").is_err());
+
+        // Headers are only listed without a query and keep their flag in the output
+        let list = ElementList::from(
+            parse_json(r#"[{"name": "Fruit", "selectable": false}, {"name": "Figs"}]"#).unwrap(),
+        );
+        assert!(list.get(0).unwrap().header);
+        assert_eq!(
+            list.search("", &config::SearchConfig::default()),
+            vec![0, 1]
+        );
+        assert_eq!(list.search("f", &config::SearchConfig::default()), vec![1]);
+        assert_eq!(
+            serde_json::to_string(&JsonItem::from(list.get(0).unwrap())).unwrap(),
+            r#"{"name":"Fruit","value":"Fruit","score":0,"selectable":false}"#
+        );
+    }
+
+    #[test]
+    fn parse_desktop_file_actions() {
+        let content = r#"[Desktop Entry]
+Name=Firefox
+Name[de]=Firefox Webbrowser
+Exec=/usr/lib/firefox/firefox %u
+Icon=firefox
+Type=Application
+Comment=Browse the World Wide Web
+Comment[de]=Im Internet surfen
+GenericName=Web Browser
+GenericName[de]=Webbrowser
+Keywords=Internet;WWW;Browser;Web;Explorer;
+Keywords[de]=Internet;WWW;Browser;Web;Explorer;Webseite;
+Actions=new-window;new-private-window;missing;
+
+[Desktop Action new-window]
+Name=New Window
+Exec=/usr/lib/firefox/firefox --new-window %u
+
+[Desktop Action new-private-window]
+Name=New Private Window
+Exec=/usr/lib/firefox/firefox --private-window %u
+"#;
+        let elements = ElementListBuilder::parse_desktop_file(
+            content,
+            Path::new("firefox.desktop"),
+            &Environment::default(),
+        );
+        let names: Vec<&str> = elements.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "Firefox",
+                "Firefox: New Window",
+                "Firefox: New Private Window"
+            ]
+        );
+        assert_eq!(elements[0].icon.as_deref(), Some("firefox"));
+        assert_eq!(elements[0].generic_name.as_deref(), Some("Web Browser"));
+        assert_eq!(elements[0].keywords.len(), 5);
+        assert_eq!(
+            elements[2].argv,
+            Some(vec![
+                "/usr/lib/firefox/firefox".to_string(),
+                "--private-window".to_string()
+            ])
+        );
+        assert_eq!(
+            elements[0].comment.as_deref(),
+            Some("Browse the World Wide Web")
+        );
+
+        let german = ElementListBuilder::parse_desktop_file(
+            content,
+            Path::new("firefox.desktop"),
+            &Environment::from_env(Some("de_DE")),
+        );
+        assert_eq!(german[0].name, "Firefox Webbrowser");
+        assert_eq!(german[0].comment.as_deref(), Some("Im Internet surfen"));
+        assert_eq!(german[0].generic_name.as_deref(), Some("Webbrowser"));
+        assert!(german[0].keywords.contains(&"Webseite".to_string()));
+
+        let hidden = content.replace("Type=Application", "Hidden=true");
+        assert!(ElementListBuilder::parse_desktop_file(
+            &hidden,
+            Path::new("firefox.desktop"),
+            &Environment::default()
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn flatpak_without_exported() {
+        let flatpak = |app_id: &str| Element {
+            name: app_id.to_string(),
+            value: format!("flatpak run {app_id}"),
+            ..Element::default()
+        };
+        let exported = HashSet::from(["org.gimp.GIMP.desktop".to_string()]);
+        let elements = ElementListBuilder::without_exported(
+            vec![flatpak("org.gimp.GIMP"), flatpak("org.gnome.Maps")],
+            &exported,
+        );
+        assert_eq!(elements, vec![flatpak("org.gnome.Maps")]);
+    }
+
+    #[test]
+    fn search_secondary_fields() {
+        let content = r#"[Desktop Entry]
+Name=Firefox
+Exec=firefox %u
+Terminal=false
+Keywords=Internet;WWW;Browser;Web;Explorer;
+Categories=Network;WebBrowser;
+"#;
+        let mut inner = ElementListBuilder::parse_desktop_file(
+            content,
+            Path::new("firefox.desktop"),
+            &Environment::default(),
+        );
+        inner.push(Element {
+            name: "browser-sync".to_string(),
+            value: "browser-sync".to_string(),
+            ..Element::default()
+        });
+        assert!(!inner[0].terminal);
+        let list = ElementList { inner };
+        let config = config::SearchConfig::default();
+
+        let names = |pattern| {
+            list.search(pattern, &config)
+                .iter()
+                .map(|index| list.inner[*index].name.clone())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(names("browser"), vec!["browser-sync", "Firefox"]);
+        assert_eq!(names("fire"), vec!["Firefox"]);
+        assert_eq!(
+            match_positions("Firefox", "fire", &config),
+            vec![0, 1, 2, 3]
+        );
+
+        let config = config::SearchConfig {
+            keywords_weight: 0.,
+            categories_weight: 0.,
+            ..config::SearchConfig::default()
+        };
+        let results = list.search("browser", &config);
+        assert_eq!(results.len(), 1);
+        assert_eq!(list.inner[results[0]].name, "browser-sync");
+        assert!(match_positions("Firefox", "browser", &config).is_empty());
+    }
+
+    #[test]
+    fn parse_desktop_file_conditions() {
+        let env = Environment {
+            locale: None,
+            current_desktops: vec!["sway".to_string()],
+        };
+        let parse = |content: &str| {
+            ElementListBuilder::parse_desktop_file(content, Path::new("test.desktop"), &env)
+        };
+
+        let content = "[Desktop Entry]\nName=Test\nExec=test\nPath=/tmp/work\n";
+        assert_eq!(
+            parse(content)[0].working_dir,
+            Some(PathBuf::from("/tmp/work"))
+        );
+        assert_eq!(parse(&format!("{content}OnlyShowIn=sway;\n")).len(), 1);
+        assert!(parse(&format!("{content}OnlyShowIn=GNOME;\n")).is_empty());
+        assert!(parse(&format!("{content}NotShowIn=sway;\n")).is_empty());
+        assert!(parse(&format!("{content}TryExec=kickoff-does-not-exist\n")).is_empty());
+        assert_eq!(parse(&format!("{content}TryExec=/bin/sh\n")).len(), 1);
+    }
 }
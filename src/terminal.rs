@@ -0,0 +1,80 @@
+use crate::desktop;
+use std::env;
+use std::path::Path;
+
+/// Terminal emulators tried in order if none is configured, together with
+/// the arguments they need in front of the command to execute.
+const KNOWN_TERMINALS: &[(&str, &[&str])] = &[
+    ("xdg-terminal-exec", &[]),
+    ("foot", &[]),
+    ("kitty", &[]),
+    ("alacritty", &["-e"]),
+    ("wezterm", &["start", "--"]),
+    ("ghostty", &["-e"]),
+    ("konsole", &["-e"]),
+    ("gnome-terminal", &["--"]),
+    ("xfce4-terminal", &["-x"]),
+    ("xterm", &["-e"]),
+];
+
+/// Returns the command prefix used to run a program inside a terminal.
+///
+/// A configured terminal is used as is, split at whitespace. Otherwise `$TERMINAL`
+/// and the known terminal emulators are checked in that order.
+pub fn command(configured: Option<&str>) -> Option<Vec<String>> {
+    if let Some(configured) = configured {
+        return Some(configured.split_whitespace().map(str::to_string).collect());
+    }
+
+    let from_env = env::var("TERMINAL")
+        .ok()
+        .filter(|terminal| !terminal.is_empty());
+    if let Some(terminal) = from_env {
+        let name = Path::new(&terminal)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let args: &[&str] = KNOWN_TERMINALS
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map_or(&["-e"], |(_, args)| args);
+        return Some(with_args(&terminal, args));
+    }
+
+    KNOWN_TERMINALS
+        .iter()
+        .find(|(name, _)| desktop::executable_exists(name))
+        .map(|(name, args)| with_args(name, args))
+}
+
+/// Wraps a command so that it runs inside the given terminal.
+pub fn wrap(terminal: &[String], argv: &[String]) -> Vec<String> {
+    terminal.iter().chain(argv).cloned().collect()
+}
+
+fn with_args(terminal: &str, args: &[&str]) -> Vec<String> {
+    std::iter::once(terminal)
+        .chain(args.iter().copied())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configured_terminal() {
+        assert_eq!(
+            command(Some("wezterm start --")).unwrap(),
+            vec!["wezterm", "start", "--"]
+        );
+        assert_eq!(
+            wrap(
+                &command(Some("alacritty -e")).unwrap(),
+                &["htop".to_string()]
+            ),
+            vec!["alacritty", "-e", "htop"]
+        );
+    }
+}
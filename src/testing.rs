@@ -0,0 +1,30 @@
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// A directory in the system temp dir for the files of a test, removed again
+/// when dropped, even if the test panics.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates `kickoff-<name>-<pid>`, the name has to be unique among the tests.
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("kickoff-{name}-{}", std::process::id()));
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}